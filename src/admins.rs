@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, UserId},
+    Bot, RequestError,
+};
+use tokio::sync::Mutex;
+
+struct CachedAdmins {
+    ids: HashSet<UserId>,
+    fetched_at: Instant,
+}
+
+/// Administrators of chats, so they aren't requested from Telegram on every update.
+/// Cached lists are refreshed once they are older than the ttl and dropped once someone is promoted or demoted.
+pub struct AdminCache {
+    ttl: Duration,
+    chats: Mutex<HashMap<ChatId, CachedAdmins>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl AdminCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            chats: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn is_admin(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<bool, RequestError> {
+        if let Some(cached) = self.chats.lock().await.get(&chat_id) {
+            if cached.fetched_at.elapsed() < self.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                return Ok(cached.ids.contains(&user_id));
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        match self.refresh(bot, chat_id).await {
+            Ok(ids) => Ok(ids.contains(&user_id)),
+            Err(err) => {
+                //
+                // a stale list is better than treating an admin as a regular user
+                //
+                match self.chats.lock().await.get(&chat_id) {
+                    Some(cached) => {
+                        log::warn!(
+                            "Failed to refresh administrators of chat ({}), a stale list is used: {}",
+                            chat_id,
+                            err
+                        );
+
+                        Ok(cached.ids.contains(&user_id))
+                    }
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    /// Requests administrators of the chat from Telegram and caches them.
+    pub async fn refresh(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+    ) -> Result<HashSet<UserId>, RequestError> {
+        let ids = match bot.get_chat_administrators(chat_id).await {
+            Ok(admins) => admins
+                .into_iter()
+                .map(|admin| admin.user.id)
+                .collect::<HashSet<_>>(),
+            Err(err) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+
+                return Err(err);
+            }
+        };
+        log::debug!(
+            "{} administrators of chat ({}) are cached",
+            ids.len(),
+            chat_id
+        );
+
+        self.chats.lock().await.insert(
+            chat_id,
+            CachedAdmins {
+                ids: ids.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+
+        Ok(ids)
+    }
+
+    pub async fn invalidate(&self, chat_id: ChatId) {
+        if self.chats.lock().await.remove(&chat_id).is_some() {
+            log::debug!("Cached administrators of chat ({}) are dropped", chat_id);
+        }
+    }
+
+    /// Numbers of cache hits, misses and failed requests in the way they are shown in work chat.
+    pub fn describe_stats(&self) -> String {
+        format!(
+            "админы: попаданий {}, промахов {}, ошибок запросов {}",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.errors.load(Ordering::Relaxed)
+        )
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::UnbanChatMemberSetters,
+    requests::Requester,
+    types::{ChatId, ChatInviteLink, InlineKeyboardButton, InlineKeyboardMarkup, UserId},
+    Bot, RequestError,
+};
+
+use crate::{policy::lift_restrictions, storage::Storage};
+
+pub const UNDO_CALLBACK_PREFIX: &str = "undo:";
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Action {
+    /// User was removed from chat, but is able to join it again.
+    Kick,
+    /// User was removed from chat and banned in it.
+    Ban,
+    /// User was removed from chat and banned in it for a while.
+    TempBan { until: DateTime<Utc> },
+    /// User isn't able to write into chat until the date, or forever if there is no date.
+    Restrict { until: Option<DateTime<Utc>> },
+}
+
+impl Action {
+    pub fn describe(&self) -> String {
+        match self {
+            Action::Kick => "удален из чата".to_owned(),
+            Action::Ban => "удален из чата и заблокирован".to_owned(),
+            Action::TempBan { until } => {
+                format!(
+                    "удален из чата и заблокирован до {}",
+                    until.format(DATE_FORMAT)
+                )
+            }
+            Action::Restrict { until: None } => "может только читать чат".to_owned(),
+            Action::Restrict { until: Some(until) } => {
+                format!("не может писать в чат до {}", until.format(DATE_FORMAT))
+            }
+        }
+    }
+
+    /// Returns `false` if user stays in chat after the action.
+    pub fn removes_from_chat(&self) -> bool {
+        !matches!(self, Action::Restrict { .. })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    /// User's name as it was shown in the notification.
+    pub user: String,
+    pub action: Action,
+    pub date: DateTime<Utc>,
+    pub undone: bool,
+    /// Temporary ban has expired and Telegram has lifted it.
+    #[serde(default)]
+    pub lifted: bool,
+    /// Invite link the user joined the chat through, see [`JoinLink::describe`].
+    #[serde(default)]
+    pub invite_link: Option<String>,
+}
+
+/// Invite link a user joined the chat through.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JoinLink {
+    /// Telegram hides the end of links which are not created by Bot.
+    pub url: String,
+    pub name: Option<String>,
+    pub creator_id: UserId,
+    pub creator: String,
+}
+
+impl JoinLink {
+    pub fn new(link: &ChatInviteLink) -> Self {
+        Self {
+            url: link.invite_link.clone(),
+            name: link.name.clone(),
+            creator_id: link.creator.id,
+            creator: link.creator.username.as_ref().map_or_else(
+                || link.creator.first_name.clone(),
+                |username| format!("@{}", username),
+            ),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("«{}» ({}) от {}", name, self.url, self.creator),
+            None => format!("{} от {}", self.url, self.creator),
+        }
+    }
+
+    /// Returns `true` if the pattern is the name or the URL of the link.
+    pub fn matches(&self, pattern: &str) -> bool {
+        self.name.as_deref() == Some(pattern) || self.url == pattern
+    }
+}
+
+/// Automated actions of Bot, so admins are able to reverse them.
+#[derive(Default, Serialize, Deserialize)]
+pub struct AuditLog {
+    last_id: u64,
+    entries: BTreeMap<u64, Entry>,
+    /// Users pardoned before pardons got an expiry, see [`AuditLog::migrate_pardons`].
+    #[serde(default, skip_serializing)]
+    pardoned: HashSet<UserId>,
+    /// Users whose removal was undone, until when they pass the check.
+    #[serde(default)]
+    pardons: HashMap<UserId, DateTime<Utc>>,
+    /// Dates users failed the check, see [`AuditLog::add_strike`].
+    #[serde(default)]
+    strikes: HashMap<UserId, Vec<DateTime<Utc>>>,
+    /// Invite links users joined the chat through last time.
+    #[serde(default)]
+    join_links: HashMap<UserId, JoinLink>,
+}
+
+impl AuditLog {
+    pub fn record(
+        &mut self,
+        chat_id: ChatId,
+        user_id: UserId,
+        user: String,
+        action: Action,
+    ) -> u64 {
+        self.last_id += 1;
+
+        let id = self.last_id;
+        let invite_link = self.join_links.get(&user_id).map(JoinLink::describe);
+        self.entries.insert(
+            id,
+            Entry {
+                id,
+                chat_id,
+                user_id,
+                user,
+                action,
+                date: Utc::now(),
+                undone: false,
+                lifted: false,
+                invite_link,
+            },
+        );
+
+        id
+    }
+
+    pub fn is_pardoned(&self, user_id: UserId) -> bool {
+        self.pardons
+            .get(&user_id)
+            .is_some_and(|&until| until > Utc::now())
+    }
+
+    /// The user passes the check until the date. Expired pardons are forgotten.
+    pub fn pardon(&mut self, user_id: UserId, until: DateTime<Utc>) {
+        let now = Utc::now();
+
+        self.pardons.retain(|_, &mut until| until > now);
+        self.pardons.insert(user_id, until);
+    }
+
+    /// Returns `false` if the user wasn't pardoned.
+    pub fn revoke_pardon(&mut self, user_id: UserId) -> bool {
+        self.pardons
+            .remove(&user_id)
+            .is_some_and(|until| until > Utc::now())
+    }
+
+    /// Pardons which were given forever expire at the date.
+    pub fn migrate_pardons(&mut self, until: DateTime<Utc>) {
+        for user_id in std::mem::take(&mut self.pardoned) {
+            self.pardons.entry(user_id).or_insert(until);
+        }
+    }
+
+    pub fn has_legacy_pardons(&self) -> bool {
+        !self.pardoned.is_empty()
+    }
+
+    /// Remembers the link the user joined the chat through, or forgets the previous one if there is no link.
+    pub fn remember_join_link(&mut self, user_id: UserId, link: Option<JoinLink>) {
+        match link {
+            Some(link) => self.join_links.insert(user_id, link),
+            None => self.join_links.remove(&user_id),
+        };
+    }
+
+    pub fn join_link(&self, user_id: UserId) -> Option<&JoinLink> {
+        self.join_links.get(&user_id)
+    }
+
+    /// Counts one more time the user failed the check. Returns how many times it happened within the window.
+    /// Strikes out of the window are forgotten for every user, so users who failed once don't stay in the log forever.
+    pub fn add_strike(&mut self, user_id: UserId, window: Duration) -> usize {
+        let now = Utc::now();
+        let since = now - chrono::Duration::seconds(window.as_secs() as i64);
+
+        self.strikes.retain(|_, strikes| {
+            strikes.retain(|&date| date > since);
+
+            !strikes.is_empty()
+        });
+
+        let strikes = self.strikes.entry(user_id).or_default();
+        strikes.push(now);
+
+        strikes.len()
+    }
+
+    /// Returns `false` if the user had no strikes.
+    pub fn reset_strikes(&mut self, user_id: UserId) -> bool {
+        self.strikes.remove(&user_id).is_some()
+    }
+
+    /// Bans issued by Bot which are neither undone nor expired, the oldest first.
+    pub fn active_bans(&self) -> Vec<&Entry> {
+        self.entries
+            .values()
+            .filter(|entry| {
+                matches!(entry.action, Action::Ban | Action::TempBan { .. })
+                    && !entry.undone
+                    && !entry.lifted
+            })
+            .collect()
+    }
+
+    /// Marks temporary bans which have expired by the date as lifted and returns them.
+    pub fn lift_expired_bans(&mut self, now: DateTime<Utc>) -> Vec<Entry> {
+        self.entries
+            .values_mut()
+            .filter(|entry| {
+                matches!(entry.action, Action::TempBan { until } if until <= now)
+                    && !entry.undone
+                    && !entry.lifted
+            })
+            .map(|entry| {
+                entry.lifted = true;
+
+                entry.clone()
+            })
+            .collect()
+    }
+}
+
+pub enum UndoOutcome {
+    NotFound,
+    AlreadyUndone(Entry),
+    Undone(Entry),
+}
+
+impl UndoOutcome {
+    pub fn describe(&self, id: u64) -> String {
+        match self {
+            UndoOutcome::NotFound => format!("Действие #{} не найдено", id),
+            UndoOutcome::AlreadyUndone(entry) => {
+                format!("Действие #{} ({}) уже было отменено", id, entry.user)
+            }
+            UndoOutcome::Undone(entry) if entry.action.removes_from_chat() => format!(
+                "Действие #{} отменено, {} может снова вступить в чат",
+                id, entry.user
+            ),
+            UndoOutcome::Undone(entry) => format!(
+                "Действие #{} отменено, {} снова может писать в чат",
+                id, entry.user
+            ),
+        }
+    }
+}
+
+/// The user is added to the data of the button, so pressing it is handled in turn with other updates about the user.
+pub fn undo_keyboard(id: u64, user_id: UserId) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        format!("Отменить (#{})", id),
+        format!("{}{}:{}", UNDO_CALLBACK_PREFIX, id, user_id),
+    )]])
+}
+
+/// Reverses an action by calling Telegram methods which are inverse to the ones called by the action.
+/// The user passes the check until the date, so they aren't removed again right away.
+pub async fn undo(
+    bot: &Bot,
+    audit: &Storage<AuditLog>,
+    id: u64,
+    pardon_until: DateTime<Utc>,
+) -> Result<UndoOutcome, RequestError> {
+    let Some(entry) = audit.read(|log| log.entries.get(&id).cloned()).await else {
+        return Ok(UndoOutcome::NotFound);
+    };
+
+    if entry.undone {
+        return Ok(UndoOutcome::AlreadyUndone(entry));
+    }
+
+    match entry.action {
+        //
+        // kicked user isn't banned normally, but kicking is done by banning, so make sure ban is lifted
+        //
+        Action::Kick | Action::Ban | Action::TempBan { .. } => {
+            bot.unban_chat_member(entry.chat_id, entry.user_id)
+                .only_if_banned(true)
+                .await?;
+            log::debug!(
+                "{} has been unbanned in chat ({})",
+                entry.user_id,
+                entry.chat_id
+            );
+        }
+        Action::Restrict { .. } => {
+            lift_restrictions(bot, entry.chat_id, entry.user_id).await?;
+        }
+    }
+
+    audit
+        .update(|log| {
+            if let Some(entry) = log.entries.get_mut(&id) {
+                entry.undone = true;
+            }
+            log.pardon(entry.user_id, pardon_until);
+        })
+        .await;
+
+    Ok(UndoOutcome::Undone(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use teloxide::types::UserId;
+
+    use super::AuditLog;
+
+    #[test]
+    fn strikes_out_of_window_are_forgotten() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut log = AuditLog::default();
+        log.strikes
+            .insert(UserId(1), vec![Utc::now() - chrono::Duration::days(2)]);
+        log.strikes.insert(
+            UserId(2),
+            vec![Utc::now() - chrono::Duration::days(2), Utc::now()],
+        );
+
+        assert_eq!(log.add_strike(UserId(3), day), 1);
+        assert_eq!(log.add_strike(UserId(3), day), 2);
+
+        assert!(!log.strikes.contains_key(&UserId(1)));
+        assert_eq!(log.strikes[&UserId(2)].len(), 1);
+        assert_eq!(log.add_strike(UserId(2), day), 2);
+    }
+}
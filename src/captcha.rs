@@ -0,0 +1,138 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeSet},
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, User, UserId},
+    Bot, RequestError,
+};
+
+use crate::{
+    config::Config,
+    misc::{date_after, describe_duration, describe_user},
+    quarantine::Quarantine,
+    storage::Storage,
+};
+
+pub const CAPTCHA_CALLBACK_PREFIX: &str = "captcha:";
+
+/// An arithmetic question posted to chat for a newcomer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Challenge {
+    pub user: User,
+    pub answer: u8,
+    pub message_id: MessageId,
+    pub deadline: DateTime<Utc>,
+}
+
+/// Data of a pressed button: "<user id>:<chosen option>".
+#[derive(Clone)]
+pub struct Answer {
+    pub user_id: UserId,
+    pub option: u8,
+}
+
+impl FromStr for Answer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user_id, option) = s
+            .split_once(':')
+            .ok_or_else(|| format!("wrong answer: {}", s))?;
+
+        Ok(Self {
+            user_id: UserId(
+                user_id
+                    .parse()
+                    .map_err(|err| format!("wrong answer {}: {}", s, err))?,
+            ),
+            option: option
+                .parse()
+                .map_err(|err| format!("wrong answer {}: {}", s, err))?,
+        })
+    }
+}
+
+/// A number in `0..bound`. It doesn't need to be strong, it only makes questions differ.
+fn random(bound: u64) -> u64 {
+    RandomState::new().build_hasher().finish() % bound
+}
+
+/// Posts a question with buttons to chat. The user stays in quarantine until they answer it.
+pub async fn challenge(
+    bot: &Bot,
+    cfg: &Config,
+    quarantine: &Storage<Quarantine>,
+    user: &User,
+) -> Result<(), RequestError> {
+    let (a, b) = (random(9) as u8 + 1, random(9) as u8 + 1);
+    let answer = a + b;
+
+    let mut options = BTreeSet::from([answer]);
+    while options.len() < 4 {
+        options.insert(random(17) as u8 + 2);
+    }
+
+    let keyboard = InlineKeyboardMarkup::new([options.into_iter().map(|option| {
+        InlineKeyboardButton::callback(
+            option.to_string(),
+            format!("{}{}:{}", CAPTCHA_CALLBACK_PREFIX, user.id, option),
+        )
+    })]);
+
+    let message = bot
+        .send_message(
+            cfg.channel_chat_id,
+            format!(
+                "{}, чтобы писать в чат, решите пример: {} + {} = ?\nНа ответ есть {}.",
+                describe_user(user),
+                a,
+                b,
+                describe_duration(chrono::Duration::seconds(
+                    cfg.captcha_timeout.as_secs() as i64
+                ))
+            ),
+        )
+        .reply_markup(keyboard)
+        .await?;
+    log::debug!(
+        "Captcha ({}) was sent to {} in chat ({})",
+        message.id,
+        user.id,
+        cfg.channel_chat_id
+    );
+
+    quarantine
+        .update(|quarantine| {
+            quarantine.set_challenge(Challenge {
+                user: user.clone(),
+                answer,
+                message_id: message.id,
+                deadline: date_after(cfg.captcha_timeout),
+            })
+        })
+        .await;
+
+    Ok(())
+}
+
+/// Deletes the question from chat, the user isn't able to answer it anymore.
+pub async fn clean_up(bot: &Bot, cfg: &Config, challenge: &Challenge) {
+    if let Err(err) = bot
+        .delete_message(cfg.channel_chat_id, challenge.message_id)
+        .await
+    {
+        log::error!(
+            "Failed to delete captcha ({}) in chat ({}): {}",
+            challenge.message_id,
+            cfg.channel_chat_id,
+            err
+        );
+    }
+}
@@ -1,37 +1,128 @@
-use std::env;
+use std::{env, fmt::Display, path::PathBuf, str::FromStr, time::Duration};
 
 use teloxide::types::{ChatId, UserId};
 
+use crate::policy::{Measure, RestrictionHours};
+
 pub struct Config {
     pub channel_id: ChatId,
     pub channel_chat_id: ChatId,
     pub work_chat_id: ChatId,
     pub maintainer_id: Option<UserId>,
+    pub data_dir: PathBuf,
+    pub reconcile_interval: Option<Duration>,
+    pub reconcile_delay: Duration,
+    pub message_policy: MessagePolicy,
+    pub delete_offending_messages: bool,
+    pub warn_about_deleted_messages: bool,
+    pub verified_ttl: Duration,
+    /// How long a user whose removal was undone passes the check.
+    pub pardon_ttl: Duration,
+    pub sender_chat_policy: SenderChatPolicy,
+    pub allowed_sender_chats: Vec<ChatId>,
+    pub admission_rules: String,
+    pub action_on_join: Measure,
+    pub action_on_invite: Measure,
+    pub action_on_channel_leave: Measure,
+    pub action_on_message: Measure,
+    pub notify_about_lifted_bans: bool,
+    pub quarantine_newcomers: bool,
+    pub captcha: bool,
+    pub captcha_timeout: Duration,
+    pub raid_joins: Option<usize>,
+    pub raid_window: Duration,
+    pub lockdown_duration: Duration,
+    pub lockdown_read_only: bool,
+    pub strike_window: Duration,
+    pub strike_escalation: Vec<Measure>,
+    pub strike_flag_after: usize,
+    pub strike_quiet_after: Option<usize>,
+    /// Ids or usernames of bots which can be added to channel's chat.
+    pub allowed_bots: Vec<String>,
+    pub invite_enforcement: InviteEnforcement,
+    pub invite_confirmation_timeout: Duration,
+    pub invites_report: bool,
+    pub admin_cache_ttl: Duration,
+    pub membership_cache_ttl: Duration,
+    pub compare_membership_cache: bool,
+    /// Subscribers can get a single-use link to channel's chat from Bot.
+    pub personal_invites: bool,
+    pub personal_invite_ttl: Duration,
+}
+
+impl Config {
+    /// Measure for a user who failed the check the given number of times.
+    /// The first time the measure of the event is taken, then measures of escalation follow one by one.
+    pub fn escalate(&self, strikes: usize, measure: Measure) -> Measure {
+        if strikes <= 1 {
+            return measure;
+        }
+
+        self.strike_escalation
+            .get(strikes - 2)
+            .or(self.strike_escalation.last())
+            .copied()
+            .unwrap_or(measure)
+    }
+
+    /// Returns `true` if work chat isn't notified about a user who failed the check the given number of times.
+    pub fn is_quiet(&self, strikes: usize) -> bool {
+        self.strike_quiet_after
+            .is_some_and(|quiet_after| strikes > quiet_after)
+    }
+}
+
+/// What to do with messages in channel's chat sent by users who haven't been checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessagePolicy {
+    /// Messages aren't checked.
+    Off,
+    /// Sender is checked and removed from chat if they are not a member of channel.
+    Remove,
+    /// Anyone can comment posts, but messages beyond comments of those who are not members of channel are deleted.
+    CommentsOnly,
+}
+
+impl FromStr for MessagePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "remove" => Ok(Self::Remove),
+            "comments-only" => Ok(Self::CommentsOnly),
+            _ => Err(format!("unknown message policy: {}", s)),
+        }
+    }
 }
 
 impl Config {
     pub fn new() -> Self {
+        let ban_duration = optional::<RestrictionHours>("BAN_DURATION_HOURS").map(|hours| hours.0);
+
         Self {
             channel_id: ChatId({
                 const CHANNEL_ID: &str = "CHANNEL_ID";
                 env::var(CHANNEL_ID)
-                    .expect(format!("{} must be specified", CHANNEL_ID).as_str())
+                    .unwrap_or_else(|_| panic!("{} must be specified", CHANNEL_ID))
                     .parse()
-                    .expect(format!("Failed to parse {} value into i64", CHANNEL_ID).as_str())
+                    .unwrap_or_else(|_| panic!("Failed to parse {} value into i64", CHANNEL_ID))
             }),
             channel_chat_id: ChatId({
                 const CHANNEL_CHAT_ID: &str = "CHANNEL_CHAT_ID";
                 env::var(CHANNEL_CHAT_ID)
-                    .expect(format!("{} must be specified", CHANNEL_CHAT_ID).as_str())
+                    .unwrap_or_else(|_| panic!("{} must be specified", CHANNEL_CHAT_ID))
                     .parse()
-                    .expect(format!("Failed to parse {} value into i64", CHANNEL_CHAT_ID).as_str())
+                    .unwrap_or_else(|_| {
+                        panic!("Failed to parse {} value into i64", CHANNEL_CHAT_ID)
+                    })
             }),
             work_chat_id: ChatId({
                 const WORK_CHAT_ID: &str = "WORK_CHAT_ID";
                 env::var(WORK_CHAT_ID)
-                    .expect(format!("{} must be specified", WORK_CHAT_ID).as_str())
+                    .unwrap_or_else(|_| panic!("{} must be specified", WORK_CHAT_ID))
                     .parse()
-                    .expect(format!("Failed to parse {} value into i64", WORK_CHAT_ID).as_str())
+                    .unwrap_or_else(|_| panic!("Failed to parse {} value into i64", WORK_CHAT_ID))
             }),
             maintainer_id: {
                 const MAINTAINER_ID: &str = "MAINTAINER_ID";
@@ -55,6 +146,292 @@ impl Config {
                     }
                 }
             },
+            data_dir: {
+                const DATA_DIR: &str = "DATA_DIR";
+                env::var(DATA_DIR).map_or_else(
+                    |_| {
+                        log::info!("{} was not provided, ./data is used", DATA_DIR);
+
+                        PathBuf::from("data")
+                    },
+                    PathBuf::from,
+                )
+            },
+            reconcile_interval: optional::<u64>("RECONCILE_INTERVAL_SECS").map(Duration::from_secs),
+            reconcile_delay: Duration::from_millis(optional("RECONCILE_DELAY_MS").unwrap_or(200)),
+            message_policy: optional("MESSAGE_POLICY").unwrap_or(MessagePolicy::Off),
+            delete_offending_messages: optional("DELETE_OFFENDING_MESSAGES").unwrap_or(true),
+            warn_about_deleted_messages: optional("WARN_ABOUT_DELETED_MESSAGES").unwrap_or(false),
+            verified_ttl: Duration::from_secs(
+                optional("VERIFIED_TTL_SECS").unwrap_or(24 * 60 * 60),
+            ),
+            pardon_ttl: Duration::from_secs(
+                optional("PARDON_TTL_HOURS").unwrap_or(7 * 24) * 60 * 60,
+            ),
+            sender_chat_policy: optional("SENDER_CHAT_POLICY").unwrap_or(SenderChatPolicy::Allow),
+            allowed_sender_chats: list("ALLOWED_SENDER_CHATS")
+                .into_iter()
+                .map(ChatId)
+                .collect(),
+            admission_rules: env::var("ADMISSION_RULES")
+                .unwrap_or_else(|_| "subscribed".to_owned()),
+            action_on_join: measure("ACTION_ON_JOIN", Measure::Ban(None))
+                .with_ban_duration(ban_duration),
+            action_on_invite: measure("ACTION_ON_INVITE", Measure::Notify)
+                .with_ban_duration(ban_duration),
+            action_on_channel_leave: optional("ACTION_ON_CHANNEL_LEAVE")
+                .unwrap_or(Measure::Mirror)
+                .with_ban_duration(ban_duration),
+            action_on_message: measure("ACTION_ON_MESSAGE", Measure::Ban(None))
+                .with_ban_duration(ban_duration),
+            notify_about_lifted_bans: optional("NOTIFY_ABOUT_LIFTED_BANS").unwrap_or(false),
+            quarantine_newcomers: optional("QUARANTINE_NEWCOMERS").unwrap_or(false),
+            captcha: optional("CAPTCHA").unwrap_or(false),
+            captcha_timeout: Duration::from_secs(
+                optional("CAPTCHA_TIMEOUT_SECS").unwrap_or(5 * 60),
+            ),
+            raid_joins: optional("RAID_JOINS"),
+            raid_window: Duration::from_secs(optional("RAID_WINDOW_SECS").unwrap_or(60)),
+            lockdown_duration: Duration::from_secs(optional("LOCKDOWN_SECS").unwrap_or(30 * 60)),
+            lockdown_read_only: optional("LOCKDOWN_READ_ONLY").unwrap_or(false),
+            strike_window: Duration::from_secs(
+                optional("STRIKE_WINDOW_HOURS").unwrap_or(7 * 24) * 60 * 60,
+            ),
+            strike_escalation: list("STRIKE_ESCALATION")
+                .into_iter()
+                .filter(|&measure| {
+                    if measure == Measure::Mirror {
+                        log::warn!("STRIKE_ESCALATION can't contain mirror, it's skipped");
+                    }
+
+                    measure != Measure::Mirror
+                })
+                .collect(),
+            strike_flag_after: optional("STRIKE_FLAG_AFTER").unwrap_or(3),
+            strike_quiet_after: optional("STRIKE_QUIET_AFTER"),
+            allowed_bots: list("ALLOWED_BOTS"),
+            invite_enforcement: optional("INVITE_ENFORCEMENT").unwrap_or(InviteEnforcement::Off),
+            invite_confirmation_timeout: Duration::from_secs(
+                optional("INVITE_CONFIRMATION_SECS").unwrap_or(60 * 60),
+            ),
+            invites_report: optional("INVITES_REPORT").unwrap_or(false),
+            admin_cache_ttl: Duration::from_secs(
+                optional("ADMIN_CACHE_TTL_SECS").unwrap_or(10 * 60),
+            ),
+            membership_cache_ttl: Duration::from_secs(
+                optional("MEMBERSHIP_CACHE_TTL_SECS").unwrap_or(10 * 60),
+            ),
+            compare_membership_cache: optional("COMPARE_MEMBERSHIP_CACHE").unwrap_or(false),
+            personal_invites: optional("PERSONAL_INVITES").unwrap_or(false),
+            personal_invite_ttl: Duration::from_secs(
+                optional("PERSONAL_INVITE_TTL_SECS").unwrap_or(60 * 60),
+            ),
+        }
+    }
+}
+
+/// What to do with users who failed the check, but were added by an admin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InviteEnforcement {
+    /// The action is taken right away.
+    Off,
+    /// The action is taken after a while unless another admin keeps the user.
+    Confirm,
+    /// The action is taken after a while unless the admin who added the user keeps them.
+    Acknowledge,
+}
+
+impl FromStr for InviteEnforcement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "confirm" => Ok(Self::Confirm),
+            "acknowledge" => Ok(Self::Acknowledge),
+            _ => Err(format!("unknown invite enforcement: {}", s)),
+        }
+    }
+}
+
+/// What to do with messages in channel's chat sent on behalf of other chats (not channel and not chat itself).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenderChatPolicy {
+    Allow,
+    Delete,
+    /// Message is deleted and the chat is banned, so no one can write on behalf of it anymore.
+    Ban,
+}
+
+impl FromStr for SenderChatPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "delete" => Ok(Self::Delete),
+            "ban" => Ok(Self::Ban),
+            _ => Err(format!("unknown sender chat policy: {}", s)),
         }
     }
 }
+
+/// Parses an optional variable. Its absence is fine, but an unparsable value is reported.
+fn optional<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name).ok()?.parse() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::warn!(
+                "{} is optional but it can't be parsed successfully: {}",
+                name,
+                err
+            );
+
+            None
+        }
+    }
+}
+
+/// Parses an optional measure which is applied without knowing user's status in channel.
+fn measure(name: &str, default: Measure) -> Measure {
+    match optional(name) {
+        Some(Measure::Mirror) => {
+            log::warn!("{} can't be mirror, default value is used", name);
+
+            default
+        }
+        Some(measure) => measure,
+        None => default,
+    }
+}
+
+/// Parses an optional comma separated list. Unparsable items are reported and skipped.
+fn list<T>(name: &str) -> Vec<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let Ok(value) = env::var(name) else {
+        return Vec::new();
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .filter_map(|item| match item.parse() {
+            Ok(item) => Some(item),
+            Err(err) => {
+                log::warn!(
+                    "{} contains an item which can't be parsed successfully: {}",
+                    name,
+                    err
+                );
+
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+impl Config {
+    /// Default configuration which doesn't depend on the environment.
+    pub fn for_tests() -> Self {
+        Self {
+            channel_id: ChatId(-1001),
+            channel_chat_id: ChatId(-1002),
+            work_chat_id: ChatId(-1003),
+            maintainer_id: None,
+            data_dir: PathBuf::from("data"),
+            reconcile_interval: None,
+            reconcile_delay: Duration::from_millis(200),
+            message_policy: MessagePolicy::Off,
+            delete_offending_messages: true,
+            warn_about_deleted_messages: false,
+            verified_ttl: Duration::from_secs(24 * 60 * 60),
+            pardon_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            sender_chat_policy: SenderChatPolicy::Allow,
+            allowed_sender_chats: Vec::new(),
+            admission_rules: "subscribed".to_owned(),
+            action_on_join: Measure::Ban(None),
+            action_on_invite: Measure::Notify,
+            action_on_channel_leave: Measure::Mirror,
+            action_on_message: Measure::Ban(None),
+            notify_about_lifted_bans: false,
+            quarantine_newcomers: false,
+            captcha: false,
+            captcha_timeout: Duration::from_secs(5 * 60),
+            raid_joins: None,
+            raid_window: Duration::from_secs(60),
+            lockdown_duration: Duration::from_secs(30 * 60),
+            lockdown_read_only: false,
+            strike_window: Duration::from_secs(7 * 24 * 60 * 60),
+            strike_escalation: Vec::new(),
+            strike_flag_after: 3,
+            strike_quiet_after: None,
+            allowed_bots: Vec::new(),
+            invite_enforcement: InviteEnforcement::Off,
+            invite_confirmation_timeout: Duration::from_secs(60 * 60),
+            invites_report: false,
+            admin_cache_ttl: Duration::from_secs(10 * 60),
+            membership_cache_ttl: Duration::from_secs(10 * 60),
+            compare_membership_cache: false,
+            personal_invites: false,
+            personal_invite_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Config;
+    use crate::policy::Measure::{self, *};
+
+    fn config(strike_escalation: Vec<Measure>, strike_quiet_after: Option<usize>) -> Config {
+        Config {
+            strike_escalation,
+            strike_quiet_after,
+            ..Config::for_tests()
+        }
+    }
+
+    #[test]
+    fn no_escalation() {
+        let cfg = config(Vec::new(), None);
+
+        assert_eq!(cfg.escalate(0, Notify), Notify);
+        assert_eq!(cfg.escalate(1, Notify), Notify);
+        assert_eq!(cfg.escalate(5, Kick), Kick);
+    }
+
+    #[test]
+    fn escalation() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let cfg = config(vec![Mute(day), Kick, Ban(None)], None);
+
+        assert_eq!(cfg.escalate(1, Notify), Notify);
+        assert_eq!(cfg.escalate(2, Notify), Mute(day));
+        assert_eq!(cfg.escalate(3, Notify), Kick);
+        assert_eq!(cfg.escalate(4, Notify), Ban(None));
+        //
+        // the last measure is repeated
+        //
+        assert_eq!(cfg.escalate(10, Notify), Ban(None));
+    }
+
+    #[test]
+    fn quiet() {
+        assert!(!config(Vec::new(), None).is_quiet(100));
+
+        let cfg = config(Vec::new(), Some(3));
+        assert!(!cfg.is_quiet(3));
+        assert!(cfg.is_quiet(4));
+    }
+}
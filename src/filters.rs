@@ -2,10 +2,10 @@ use std::sync::Arc;
 
 use teloxide::{
     dptree,
-    types::{ChatMemberKind, ChatMemberUpdated, Update},
+    types::{ChatMemberUpdated, Update},
 };
 
-use crate::{config::Config, misc::ReturnType};
+use crate::{config::Config, misc::ReturnType, transition::Transition};
 
 type FilterType<Output> = ReturnType<Output>;
 
@@ -23,9 +23,7 @@ pub fn filter_channel_chat_got_member<Output>() -> FilterType<Output>
 where
     Output: Send + Sync + 'static,
 {
-    filter_channel_chat().filter(|req: ChatMemberUpdated| {
-        req.old_chat_member.kind.is_left() && req.new_chat_member.kind.is_member()
-    })
+    filter_channel_chat().filter(|req: ChatMemberUpdated| Transition::of(&req).is_join())
 }
 
 pub fn filter_channel_lost_member<Output>() -> FilterType<Output>
@@ -33,11 +31,6 @@ where
     Output: Send + Sync + 'static,
 {
     dptree::filter(|req: ChatMemberUpdated, cfg: Arc<Config>| {
-        req.chat.id == cfg.channel_id
-            && req.old_chat_member.is_member()
-            && matches!(
-                req.new_chat_member.kind,
-                ChatMemberKind::Left | ChatMemberKind::Banned(_)
-            )
+        req.chat.id == cfg.channel_id && Transition::of(&req).is_departure()
     })
 }
@@ -1,79 +1,595 @@
 use std::{collections::HashSet, sync::Arc};
 
-use teloxide::{requests::Requester, respond, types::UserId, Bot, RequestError};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    respond,
+    types::{User, UserId},
+    Bot, RequestError,
+};
 use tokio::sync::Mutex;
 
-use crate::{config::Config, misc::ReturnType};
+use crate::{
+    audit::{undo, undo_keyboard, Action, AuditLog},
+    config::Config,
+    misc::{describe_duration, describe_user, ReturnType},
+    storage::Storage,
+};
 
 type HandlerType = ReturnType<Result<(), RequestError>>;
 
+/// Sends the message to work chat. If an action was taken, it's described in the message
+/// and recorded, so it can be undone by the button below the message.
+pub async fn report(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    user: &User,
+    action: Option<Action>,
+    mut message: String,
+) -> Result<(), RequestError> {
+    match action {
+        Some(action) => {
+            message.push_str(", ");
+            message.push_str(&action.describe());
+
+            let id = audit
+                .update(|log| log.record(cfg.channel_chat_id, user.id, describe_user(user), action))
+                .await;
+            log::debug!("{:?} of {} recorded as #{}", action, user.id, id);
+
+            bot.send_message(cfg.work_chat_id, message)
+                .reply_markup(undo_keyboard(id, user.id))
+                .await?;
+        }
+        None => {
+            bot.send_message(cfg.work_chat_id, message).await?;
+        }
+    }
+    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+    Ok(())
+}
+
+/// Same as [`report`], but it takes into account how many times the user failed the check.
+/// Work chat is told once the user has failed it too often, and it's not notified about them after some point.
+async fn report_strike(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    user: &User,
+    action: Option<Action>,
+    mut message: String,
+    strikes: usize,
+) -> Result<(), RequestError> {
+    if cfg.is_quiet(strikes) {
+        log::debug!(
+            "{} failed the check {} times, work chat isn't notified",
+            user.id,
+            strikes
+        );
+
+        if let Some(action) = action {
+            let id = audit
+                .update(|log| log.record(cfg.channel_chat_id, user.id, describe_user(user), action))
+                .await;
+            log::debug!("{:?} of {} recorded as #{}", action, user.id, id);
+        }
+
+        return Ok(());
+    }
+
+    message.push_str(&describe_strikes(cfg, strikes));
+    report(bot, cfg, audit, user, action, message).await?;
+    flag_offender(bot, cfg, user, strikes).await
+}
+
+/// Number of the strike to be added to a notification, empty for the first one.
+fn describe_strikes(cfg: &Config, strikes: usize) -> String {
+    if strikes > 1 {
+        format!(
+            " (нарушение №{} за {})",
+            strikes,
+            describe_duration(strike_window(cfg))
+        )
+    } else {
+        String::new()
+    }
+}
+
+/// Tells work chat about the user once they have failed the check too often.
+async fn flag_offender(
+    bot: &Bot,
+    cfg: &Config,
+    user: &User,
+    strikes: usize,
+) -> Result<(), RequestError> {
+    if strikes == cfg.strike_flag_after {
+        let mut flag = format!(
+            "Внимание: {} не прошел(а) проверку {} раз(а) за {}.",
+            describe_user(user),
+            strikes,
+            describe_duration(strike_window(cfg))
+        );
+        if cfg
+            .strike_quiet_after
+            .is_some_and(|quiet_after| quiet_after <= strikes)
+        {
+            flag.push_str(" Уведомлений о нем больше не будет.");
+        }
+        flag.push_str(&format!(" Сбросить счетчик: /resetstrikes {}", user.id));
+
+        bot.send_message(cfg.work_chat_id, flag).await?;
+        log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+    }
+
+    Ok(())
+}
+
+fn strike_window(cfg: &Config) -> chrono::Duration {
+    chrono::Duration::seconds(cfg.strike_window.as_secs() as i64)
+}
+
 pub mod update {
     use super::*;
-    use teloxide::types::{ChatMemberKind, ChatMemberUpdated};
+    use chrono::Utc;
+    use teloxide::{
+        dptree,
+        types::{ChatId, ChatMemberUpdated},
+    };
 
     use crate::{
+        admins::AdminCache,
+        audit::JoinLink,
+        captcha::challenge,
+        config::InviteEnforcement,
         filters::{filter_channel_chat_got_member, filter_channel_lost_member},
-        misc::{create_username_or_default, FormatArgument},
+        invites::{keep_invited_keyboard, InviteEvent, Invites, PendingInvite},
+        members::MemberIndex,
+        membership::MembershipCache,
+        misc::{
+            create_username_or_default, date_after, describe_chat, FormatArgument,
+            DEFAULT_USER_USERNAME,
+        },
+        policy::{apply_measure, Measure},
+        quarantine::{put_in_quarantine, release, Quarantine},
+        raid::{lock, JoinRate, Lockdown},
+        rights,
+        rules::Admission,
+        transition::Transition,
+        verified::VerifiedUsers,
     };
 
-    const DEFAULT_USER_USERNAME: &str = "(пользователь скрыл свой ID)";
+    const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
 
-    pub fn user_was_invited_to_chat_by_admin() -> HandlerType {
+    pub fn remember_member() -> HandlerType {
+        dptree::inspect_async(
+            |req: ChatMemberUpdated,
+             cfg: Arc<Config>,
+             members: Arc<Storage<MemberIndex>>,
+             membership: Arc<MembershipCache>| async move {
+                if req.chat.id == cfg.channel_chat_id || req.chat.id == cfg.channel_id {
+                    let member = req.new_chat_member;
+
+                    members
+                        .update(|index| {
+                            index.observe(req.chat.id, &member.user, Some(member.status()))
+                        })
+                        .await;
+                    membership.observe(req.chat.id, member).await;
+                }
+            },
+        )
+    }
+
+    pub fn bot_was_added_to_channel_chat() -> HandlerType {
         filter_channel_chat_got_member()
-            .filter_async(|bot: Bot, req: ChatMemberUpdated| async move {
-                let from_id = req.from.id;
-                let user_id = req.old_chat_member.user.id;
-
-                from_id != user_id
-                    && bot.get_chat_administrators(req.chat.id).await.map_or_else(
-                        |err| {
-                            log::error!(
-                                "Failed to get administrators of chat:\nchat: {:?},\nerror{}",
-                                req.chat,
-                                err
-                            );
-
-                            false
-                        },
-                        |admins| admins.into_iter().any(|admin| admin.user.id == from_id),
-                    )
+            .filter(|req: ChatMemberUpdated| req.new_chat_member.user.is_bot)
+            .inspect(|| {
+                log::debug!("bot_was_added_to_channel_chat: filters passed, calling endpoint");
             })
+            .endpoint(
+                |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>, audit: Arc<Storage<AuditLog>>| async move {
+                    let adder = req.from;
+                    let added = req.new_chat_member.user;
+
+                    let allowed = cfg.allowed_bots.iter().any(|allowed| {
+                        allowed.parse().is_ok_and(|id| added.id == UserId(id))
+                            || added
+                                .username
+                                .as_ref()
+                                .is_some_and(|username| username.eq_ignore_ascii_case(allowed.trim_start_matches('@')))
+                    });
+
+                    if allowed || audit.read(|log| log.is_pardoned(added.id)).await {
+                        log::debug!("Bot ({:?}, {}) is allowed in chat", added.username, added.id);
+
+                        bot.send_message(
+                            cfg.work_chat_id,
+                            format!(
+                                "{} добавил(а) бота {}, бот разрешен",
+                                describe_user(&adder),
+                                describe_user(&added)
+                            ),
+                        )
+                        .await?;
+                        log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+                        return respond(());
+                    }
+                    log::debug!("Bot ({:?}, {}) is NOT allowed in chat", added.username, added.id);
+
+                    let action = apply_measure(&bot, cfg.channel_chat_id, added.id, Measure::Kick, None).await?;
+
+                    report(
+                        &bot,
+                        &cfg,
+                        &audit,
+                        &added,
+                        action,
+                        format!(
+                            "{} добавил(а) бота {}, которого нет в списке разрешенных",
+                            describe_user(&adder),
+                            describe_user(&added)
+                        ),
+                    )
+                    .await?;
+
+                    //
+                    // the one who added the bot may not have started a conversation with Bot, so it's not an error
+                    //
+                    if let Err(err) = bot
+                        .send_message(
+                            adder.id,
+                            format!(
+                                "Бот {} удален из чата {}: ботов в чате могут быть только из списка разрешенных. \
+                                 Если он нужен, попросите администраторов добавить его в список.",
+                                describe_user(&added),
+                                req.chat.title().unwrap_or_default()
+                            ),
+                        )
+                        .await
+                    {
+                        log::warn!("Failed to tell ({}) that the bot was removed: {}", adder.id, err);
+                    }
+
+                    respond(())
+                },
+            )
+    }
+
+    /// Alerts maintainer and work chat once Bot is removed from one of its chats or loses its rights there,
+    /// since it can't protect the chat anymore.
+    pub fn bot_status_changed() -> HandlerType {
+        dptree::filter(|req: ChatMemberUpdated, cfg: Arc<Config>| {
+            [cfg.channel_id, cfg.channel_chat_id, cfg.work_chat_id].contains(&req.chat.id)
+        })
+        .inspect(|| {
+            log::debug!("bot_status_changed: filters passed, calling endpoint");
+        })
+        .endpoint(
+            |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>| async move {
+                let (old, new) = (&req.old_chat_member.kind, &req.new_chat_member.kind);
+                let chat = describe_chat(&req.chat);
+
+                let problem = if !new.is_present() {
+                    format!("Бот удален из {}", chat)
+                } else if old.is_privileged() && !new.is_privileged() {
+                    format!("Бот больше не админ в {}", chat)
+                } else {
+                    let (_, revoked) = rights::diff(old, new);
+                    if revoked.is_empty() {
+                        log::debug!(
+                            "Bot's status in ({}) changed, but it's fine: {:?}",
+                            req.chat.id,
+                            new
+                        );
+
+                        return respond(());
+                    }
+
+                    format!("Бот лишился прав в {}: {}", chat, revoked.join(", "))
+                };
+                let alert = format!(
+                    "{} ({}). Защита чата может не работать!",
+                    problem,
+                    describe_user(&req.from)
+                );
+                log::error!("{}", alert);
+
+                //
+                // Bot may have been removed from one of these chats, so failures are only logged
+                //
+                let recipients = cfg
+                    .maintainer_id
+                    .map(ChatId::from)
+                    .into_iter()
+                    .chain([cfg.work_chat_id]);
+                for recipient in recipients {
+                    match bot.send_message(recipient, alert.clone()).await {
+                        Ok(_) => log::debug!("A message was sent to ({})", recipient),
+                        Err(err) => {
+                            log::error!("Failed to send the alert to ({}): {}", recipient, err)
+                        }
+                    }
+                }
+
+                respond(())
+            },
+        )
+    }
+
+    /// Tells work chat who was promoted or demoted in channel or its chat and which rights were changed.
+    pub fn report_admin_changes() -> HandlerType {
+        dptree::inspect_async(
+            |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>| async move {
+                let (old, new) = (&req.old_chat_member.kind, &req.new_chat_member.kind);
+                if ![cfg.channel_id, cfg.channel_chat_id].contains(&req.chat.id)
+                    || !(old.is_privileged() || new.is_privileged())
+                {
+                    return;
+                }
+
+                let (granted, revoked) = rights::diff(old, new);
+                let (admin, user, chat) = (
+                    describe_user(&req.from),
+                    describe_user(&req.new_chat_member.user),
+                    describe_chat(&req.chat),
+                );
+
+                let mut message = match Transition::of(&req) {
+                    Transition::Promoted => {
+                        format!("{} назначил(а) админом {} в {}", admin, user, chat)
+                    }
+                    Transition::Demoted => {
+                        format!("{} снял(а) {} с должности админа в {}", admin, user, chat)
+                    }
+                    Transition::JoinedAsAdmin => {
+                        format!("{} добавил(а) {} в {} сразу админом", admin, user, chat)
+                    }
+                    Transition::Left => format!("Админ {} покинул(а) {}", user, chat),
+                    transition if transition.is_departure() => {
+                        format!("{} удалил(а) админа {} из {}", admin, user, chat)
+                    }
+                    _ if granted.is_empty() && revoked.is_empty() => {
+                        log::debug!(
+                            "Status of admin ({}) changed, but rights are the same",
+                            req.new_chat_member.user.id
+                        );
+
+                        return;
+                    }
+                    _ => format!("{} изменил(а) права админа {} в {}", admin, user, chat),
+                };
+                if !granted.is_empty() {
+                    message.push_str(&format!("\nВыданы права: {}", granted.join(", ")));
+                }
+                if !revoked.is_empty() {
+                    message.push_str(&format!("\nОтозваны права: {}", revoked.join(", ")));
+                }
+
+                match bot.send_message(cfg.work_chat_id, message).await {
+                    Ok(_) => log::debug!("A message was sent to work chat ({})", cfg.work_chat_id),
+                    Err(err) => log::error!("Failed to report the change of admin rights: {}", err),
+                }
+            },
+        )
+    }
+
+    /// Revokes a personal link once someone joined channel's chat through it.
+    /// Work chat is told if the link was used by someone else than the user it was given to.
+    pub fn revoke_used_personal_link() -> HandlerType {
+        dptree::inspect_async(
+            |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>, invites: Arc<Storage<Invites>>| async move {
+                if req.chat.id != cfg.channel_chat_id || !Transition::of(&req).is_join() {
+                    return;
+                }
+                let Some(url) = req.invite_link.as_ref().map(|link| &link.invite_link) else {
+                    return;
+                };
+                let Some(link) = invites
+                    .update(|invites| invites.take_personal_link(url))
+                    .await
+                else {
+                    return;
+                };
+
+                let user = &req.new_chat_member.user;
+                log::debug!(
+                    "({:?}, {}) joined chat through personal link of {}",
+                    user.username,
+                    user.id,
+                    link.user_id
+                );
+
+                if let Err(err) = bot
+                    .revoke_chat_invite_link(cfg.channel_chat_id, &link.url)
+                    .await
+                {
+                    log::error!("Failed to revoke personal link {}: {}", link.url, err);
+                }
+
+                if user.id != link.user_id {
+                    let message = format!(
+                        "Личной ссылкой, выданной {}, воспользовался(ась) {}",
+                        link.user,
+                        describe_user(user)
+                    );
+                    match bot.send_message(cfg.work_chat_id, message).await {
+                        Ok(_) => {
+                            log::debug!("A message was sent to work chat ({})", cfg.work_chat_id)
+                        }
+                        Err(err) => {
+                            log::error!("Failed to report the use of personal link: {}", err)
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// Forgets the invite link a user joined channel's chat through once they leave it,
+    /// so the link doesn't vouch for them if they come back another way.
+    pub fn forget_join_link() -> HandlerType {
+        dptree::inspect_async(
+            |req: ChatMemberUpdated, cfg: Arc<Config>, audit: Arc<Storage<AuditLog>>| async move {
+                let user_id = req.new_chat_member.user.id;
+                if req.chat.id != cfg.channel_chat_id
+                    || !Transition::of(&req).is_departure()
+                    || audit.read(|log| log.join_link(user_id).is_none()).await
+                {
+                    return;
+                }
+
+                audit
+                    .update(|log| log.remember_join_link(user_id, None))
+                    .await;
+                log::debug!("Invite link of {} is forgotten as they left chat", user_id);
+            },
+        )
+    }
+
+    /// Drops cached administrators of a chat once someone is promoted or demoted there.
+    pub fn forget_admins_on_promotion() -> HandlerType {
+        dptree::inspect_async(
+            |req: ChatMemberUpdated, admins: Arc<AdminCache>| async move {
+                if req.old_chat_member.is_privileged() != req.new_chat_member.is_privileged() {
+                    log::debug!(
+                        "Privileges of {} have changed in chat ({})",
+                        req.new_chat_member.user.id,
+                        req.chat.id
+                    );
+
+                    admins.invalidate(req.chat.id).await;
+                }
+            },
+        )
+    }
+
+    pub fn user_was_invited_to_chat_by_admin() -> HandlerType {
+        filter_channel_chat_got_member()
+            .filter_async(
+                |bot: Bot, req: ChatMemberUpdated, admins: Arc<AdminCache>| async move {
+                    let from_id = req.from.id;
+                    let user_id = req.old_chat_member.user.id;
+
+                    from_id != user_id
+                        && admins
+                            .is_admin(&bot, req.chat.id, from_id)
+                            .await
+                            .unwrap_or_else(|err| {
+                                log::error!(
+                                    "Failed to get administrators of chat:\nchat: {:?},\nerror{}",
+                                    req.chat,
+                                    err
+                                );
+
+                                false
+                            })
+                },
+            )
             .inspect(|| {
                 log::debug!("user_was_invited_to_chat_by_admin: filters passed, calling endpoint");
             })
             .endpoint(
-                |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>| async move {
+                |bot: Bot,
+                 req: ChatMemberUpdated,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 admission: Arc<Admission>,
+                 invites: Arc<Storage<Invites>>| async move {
                     let admin = req.from;
                     let user = req.old_chat_member.user;
 
-                    if !bot
-                        .get_chat_member(cfg.channel_id, user.id)
-                        .await?
-                        .is_member()
-                    {
+                    let failed = admission.check(&bot, &user, None).await?;
+                    invites
+                        .update(|invites| {
+                            invites.record(InviteEvent {
+                                date: Utc::now(),
+                                admin_id: admin.id,
+                                admin: describe_user(&admin),
+                                user_id: user.id,
+                                passed: failed.is_none(),
+                            })
+                        })
+                        .await;
+
+                    if let Some(rule) = failed {
                         log::debug!(
-                            "({:?}, {}) is NOT a member of channel ({})",
+                            "({:?}, {}) does NOT match rule {}",
                             user.username,
                             user.id,
-                            cfg.channel_id
+                            rule
                         );
 
-                        bot.send_message(
-                            cfg.work_chat_id,
-                            format!(
-                                "{} добавил(а) пользователя, которого нет в канале: {}",
-                                create_username_or_default(
-                                    "(админ скрыл свой ID)",
-                                    admin.username.as_ref(),
-                                ),
-                                create_username_or_default(
-                                    DEFAULT_USER_USERNAME,
-                                    user.username.as_ref(),
-                                )
+                        let strikes = audit
+                            .update(|log| log.add_strike(user.id, cfg.strike_window))
+                            .await;
+                        let message = format!(
+                            "{} добавил(а) пользователя, который не прошел проверку {}: {}",
+                            create_username_or_default(
+                                "(админ скрыл свой ID)",
+                                admin.username.as_ref(),
                             ),
-                        )
-                        .await?;
-                        log::debug!("A message was sent to work chat ({}).", cfg.work_chat_id);
+                            rule,
+                            create_username_or_default(
+                                DEFAULT_USER_USERNAME,
+                                user.username.as_ref(),
+                            )
+                        );
+
+                        //
+                        // there is nobody to ask for confirmation about users work chat isn't notified about anymore
+                        //
+                        if cfg.invite_enforcement == InviteEnforcement::Off || cfg.is_quiet(strikes)
+                        {
+                            let measure = cfg.escalate(strikes, cfg.action_on_invite);
+                            let action =
+                                apply_measure(&bot, cfg.channel_chat_id, user.id, measure, None)
+                                    .await?;
+
+                            report_strike(&bot, &cfg, &audit, &user, action, message, strikes)
+                                .await?;
+
+                            return respond(());
+                        }
+
+                        //
+                        // user stays in chat until the deadline, then the action is taken unless someone keeps them
+                        //
+                        let deadline = date_after(cfg.invite_confirmation_timeout);
+                        let who = match cfg.invite_enforcement {
+                            InviteEnforcement::Acknowledge => "добавивший его админ",
+                            _ => "другой админ",
+                        };
+
+                        let text = format!(
+                            "{}{}. Если {} не оставит его в чате до {}, будут приняты меры",
+                            message,
+                            describe_strikes(&cfg, strikes),
+                            who,
+                            deadline.format(DATE_FORMAT)
+                        );
+                        let notification = bot
+                            .send_message(cfg.work_chat_id, text.clone())
+                            .reply_markup(keep_invited_keyboard(user.id))
+                            .await?;
+                        log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+                        flag_offender(&bot, &cfg, &user, strikes).await?;
+
+                        invites
+                            .update(|invites| {
+                                invites.add(PendingInvite {
+                                    user,
+                                    admin,
+                                    message_id: notification.id,
+                                    text,
+                                    deadline,
+                                    strikes,
+                                })
+                            })
+                            .await;
                     }
 
                     respond(())
@@ -88,58 +604,181 @@ pub mod update {
                 log::debug!("user_joined_channel_chat: filters passed, calling endpoint");
             })
             .endpoint(
-                |bot: Bot, req: ChatMemberUpdated, cfg: Arc<Config>| async move {
+                |bot: Bot,
+                 req: ChatMemberUpdated,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 verified: Arc<VerifiedUsers>,
+                 admission: Arc<Admission>,
+                 quarantine: Arc<Storage<Quarantine>>,
+                 join_rate: Arc<JoinRate>,
+                 lockdown: Arc<Storage<Lockdown>>| async move {
                     let user = req.from;
-                    let channel_id = cfg.channel_id;
 
-                    if bot.get_chat_member(channel_id, user.id).await?.is_member() {
-                        log::debug!("({:?}, {}) is a member of channel ({})", user.username, user.id, channel_id);
+                    let link = req.invite_link.as_ref().map(JoinLink::new);
+                    if let Some(link) = &link {
+                        log::debug!("({:?}, {}) joined chat through link {}", user.username, user.id, link.describe());
+                    }
+                    audit.update(|log| log.remember_join_link(user.id, link)).await;
+
+                    let joins = join_rate.register().await;
+                    let raid = cfg.raid_joins.is_some_and(|threshold| joins >= threshold);
+                    let locked = lockdown.read(Lockdown::is_active).await;
+
+                    let until = date_after(cfg.lockdown_duration);
+                    if raid && !locked && lock(&bot, cfg.channel_chat_id, until, cfg.lockdown_read_only, &lockdown).await? {
+                        log::warn!("{} users joined chat within {:?}, lockdown is started", joins, cfg.raid_window);
 
                         bot.send_message(
                             cfg.work_chat_id,
                             format!(
-                                "{}{} вступление одобрено",
-                                user.first_name.end_with_comma_if_not_empty(),
-                                create_username_or_default(DEFAULT_USER_USERNAME, user.username.as_ref())
+                                "Обнаружен наплыв вступлений: {} за {} сек. Чат переведен в режим блокировки до {}: \
+                                 новые участники могут только читать чат и будут проверены после его окончания. \
+                                 Снять блокировку раньше: /unlock",
+                                joins,
+                                cfg.raid_window.as_secs(),
+                                until.format(DATE_FORMAT)
                             ),
                         )
                         .await?;
                         log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+                    }
 
-                    } else {
-                        log::debug!("({:?}, {}) is NOT a member of channel ({})", user.username, user.id, channel_id);
-
-                        bot.kick_chat_member(cfg.channel_chat_id, user.id).await?;
-                        log::debug!("({:?}, {}) has been kicked from chat ({})", user.username, user.id, cfg.channel_chat_id);
-
-                        let mut message = format!(
-                            "{}{} нет в канале, удален из чата",
-                            user.first_name.end_with_comma_if_not_empty(),
-                            create_username_or_default(DEFAULT_USER_USERNAME, user.username.as_ref())
-                        );
+                    if raid || locked {
+                        //
+                        // no checks and notifications for every newcomer during a raid
+                        //
+                        put_in_quarantine(&bot, cfg.channel_chat_id, user.id, &quarantine).await?;
+                        lockdown.update(Lockdown::count_join).await;
 
-                        if let Err(err) = bot.ban_chat_member(cfg.channel_chat_id, user.id).await {
-                            log::error!(
-                                "Partial error. Failed to ban user in chat.\nUser: {:#?}.\nChat: {}.\nError: {}",
-                                user,
-                                req.chat.id,
-                                err
-                            );
-                        } else {
-                            log::debug!("({:?}, {}) has been banned in chat ({})", user.username, user.id, cfg.channel_chat_id);
-
-                            message.push_str(" и заблокирован");
-                        }
+                        return respond(());
+                    }
 
-                        bot.send_message(cfg.work_chat_id, message).await?;
-                        log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+                    if cfg.quarantine_newcomers {
+                        put_in_quarantine(&bot, cfg.channel_chat_id, user.id, &quarantine).await?;
                     }
 
+                    check_newcomer(&bot, &cfg, &audit, &verified, &admission, &quarantine, &user).await?;
+
                     respond(())
                 },
             )
     }
 
+    /// Checks a user who joined channel's chat and notifies work chat about the result.
+    /// If the user is in quarantine, they are released once they pass the check.
+    pub async fn check_newcomer(
+        bot: &Bot,
+        cfg: &Config,
+        audit: &Storage<AuditLog>,
+        verified: &VerifiedUsers,
+        admission: &Admission,
+        quarantine: &Storage<Quarantine>,
+        user: &User,
+    ) -> Result<(), RequestError> {
+        let link = audit.read(|log| log.join_link(user.id).cloned()).await;
+        let via = link.as_ref().map_or_else(String::new, |link| {
+            format!(" по ссылке {}", link.describe())
+        });
+
+        if audit.read(|log| log.is_pardoned(user.id)).await {
+            log::debug!(
+                "({:?}, {}) was pardoned before, check is skipped",
+                user.username,
+                user.id
+            );
+
+            release(bot, cfg.channel_chat_id, user.id, quarantine).await?;
+
+            bot.send_message(
+                cfg.work_chat_id,
+                format!(
+                    "{}{} вступление{} одобрено (ранее удаление было отменено)",
+                    user.first_name.end_with_comma_if_not_empty(),
+                    create_username_or_default(DEFAULT_USER_USERNAME, user.username.as_ref()),
+                    via
+                ),
+            )
+            .await?;
+            log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+        } else if let Some(rule) = admission.check(bot, user, link.as_ref()).await? {
+            log::debug!(
+                "({:?}, {}) does NOT match rule {}",
+                user.username,
+                user.id,
+                rule
+            );
+
+            let strikes = audit
+                .update(|log| log.add_strike(user.id, cfg.strike_window))
+                .await;
+            let measure = cfg.escalate(strikes, cfg.action_on_join);
+            let action = apply_measure(bot, cfg.channel_chat_id, user.id, measure, None).await?;
+            match action {
+                //
+                // user isn't touched, so they shouldn't stay muted
+                //
+                None => release(bot, cfg.channel_chat_id, user.id, quarantine).await?,
+                //
+                // restrictions of quarantine are replaced by the action
+                //
+                Some(_) => {
+                    quarantine
+                        .update(|quarantine| quarantine.remove(user.id))
+                        .await;
+                }
+            }
+
+            report_strike(
+                bot,
+                cfg,
+                audit,
+                user,
+                action,
+                format!(
+                    "{} вступил(а){} и не прошел(а) проверку {}",
+                    describe_user(user),
+                    via,
+                    rule
+                ),
+                strikes,
+            )
+            .await?;
+        } else {
+            log::debug!("({:?}, {}) matches admission rules", user.username, user.id);
+
+            let mut message = format!(
+                "{}{} вступление{} одобрено",
+                user.first_name.end_with_comma_if_not_empty(),
+                create_username_or_default(DEFAULT_USER_USERNAME, user.username.as_ref()),
+                via
+            );
+
+            if cfg.captcha {
+                //
+                // user is verified once they answer the question
+                //
+                if !quarantine
+                    .read(|quarantine| quarantine.contains(user.id))
+                    .await
+                {
+                    put_in_quarantine(bot, cfg.channel_chat_id, user.id, quarantine).await?;
+                }
+                challenge(bot, cfg, quarantine, user).await?;
+
+                message.push_str(", ожидается ответ на капчу");
+            } else {
+                verified.verify(user.id).await;
+                release(bot, cfg.channel_chat_id, user.id, quarantine).await?;
+            }
+
+            bot.send_message(cfg.work_chat_id, message).await?;
+            log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+        }
+
+        Ok(())
+    }
+
     pub fn user_left_or_was_kicked_from_channel() -> HandlerType {
         filter_channel_lost_member()
             .inspect(|| {
@@ -149,79 +788,66 @@ pub mod update {
                 |bot: Bot,
                  req: ChatMemberUpdated,
                  cfg: Arc<Config>,
-                 ids: Arc<Mutex<HashSet<UserId>>>| async move {
+                 ids: Arc<Mutex<HashSet<UserId>>>,
+                 audit: Arc<Storage<AuditLog>>,
+                 verified: Arc<VerifiedUsers>,
+                 membership: Arc<MembershipCache>| async move {
                     let channel_chat_id = cfg.channel_chat_id;
                     let user = req.old_chat_member.user;
 
-                    if bot
-                        .get_chat_member(channel_chat_id, user.id)
-                        .await?
-                        .is_member()
-                    {
-                        log::debug!("({:?}, {}) is a member of chat ({})", user.username, user.id, channel_chat_id);
+                    verified.forget(user.id).await;
+
+                    let chat_member = membership
+                        .get_chat_member(&bot, channel_chat_id, user.id)
+                        .await?;
+                    if chat_member.is_present() && !chat_member.is_privileged() {
+                        log::debug!(
+                            "({:?}, {}) is a member of chat ({})",
+                            user.username,
+                            user.id,
+                            channel_chat_id
+                        );
 
                         let inserted = ids.lock().await.insert(user.id);
                         log::debug!("{} inserted into ids? {}!", user.id, inserted);
 
-                        match async {
-                            match req.new_chat_member.kind {
-                                ChatMemberKind::Left => {
-                                    //
-                                    // it only kicks user from chat
-                                    //
-                                    bot.unban_chat_member(channel_chat_id, user.id).await?;
-                                    log::debug!("({:?}, {}) has been only kicked (no ban) from chat ({})", user.username, user.id, channel_chat_id);
-                                }
-                                ChatMemberKind::Banned(_) => {
-                                    //
-                                    // it additionally bans user
-                                    //
-                                    bot.kick_chat_member(channel_chat_id, user.id).await?;
-                                    log::debug!("({:?}, {}) has been kicked (w/ ban) from chat ({})", user.username, user.id, channel_chat_id);
-                                }
-                                unexpected_kind => {
-                                    log::warn!(
-                                        "{:?} is not expected to be passed filters. User won't be touched.",
-                                        unexpected_kind
-                                    );
-
-                                    return Ok(false);
-                                }
-                            }
-
-                            Ok(true)
-                        }
+                        match apply_measure(
+                            &bot,
+                            channel_chat_id,
+                            user.id,
+                            cfg.action_on_channel_leave,
+                            Some(&req.new_chat_member.kind),
+                        )
                         .await
                         {
-                            Ok(success) => {
-                                if success {
-                                    log::debug!("got SUCCESS? true!");
-
-                                    bot.send_message(
-                                        cfg.work_chat_id,
-                                        format!(
-                                            "{}{} вышел из канала и был удален из чата",
-                                            user.first_name.end_with_comma_if_not_empty(),
-                                            create_username_or_default(
-                                                DEFAULT_USER_USERNAME,
-                                                user.username.as_ref(),
-                                            )
-                                        ),
-                                    )
-                                    .await?;
-                                    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
-                                } else {
-                                    log::debug!("got SUCCESS? false!");
-
+                            Ok(action) => {
+                                if !action.is_some_and(|action| action.removes_from_chat()) {
                                     //
-                                    // user hasn't been touched - no need to process left chat message
+                                    // user is still in chat - no need to process left chat message
                                     //
                                     let removed = ids.lock().await.remove(&user.id);
                                     log::debug!("{} was removed from ids? {}!", user.id, removed);
                                 }
+
+                                if action.is_some()
+                                    || cfg.action_on_channel_leave == Measure::Notify
+                                {
+                                    report(
+                                        &bot,
+                                        &cfg,
+                                        &audit,
+                                        &user,
+                                        action,
+                                        format!("{} вышел из канала", describe_user(&user)),
+                                    )
+                                    .await?;
+                                }
                             }
                             Err(err) => {
-                                log::debug!("got an error but expected success = true/false: {}", err);
+                                log::debug!(
+                                    "got an error but expected success = true/false: {}",
+                                    err
+                                );
 
                                 //
                                 // something went wrong - no need to process left chat message
@@ -242,14 +868,80 @@ pub mod update {
 
 pub mod message {
     use super::*;
+    use chrono::Utc;
+    use std::time::Duration;
     use teloxide::{
         dispatching::HandlerExt,
         dptree,
-        types::{Message, MessageKind, MessageLeftChatMember},
+        payloads::CreateChatInviteLinkSetters,
+        types::{Chat, ChatId, Message, MessageKind, MessageLeftChatMember},
         utils::command::BotCommands,
     };
 
-    use crate::filters::filter_channel_chat;
+    use crate::{
+        admins::AdminCache,
+        config::{MessagePolicy, SenderChatPolicy},
+        filters::filter_channel_chat,
+        invites::{describe_summary, Invites, PersonalLink},
+        jobs::{end_lockdown, reconcile, Reconciliation, INVITES_REPORT_DAYS},
+        members::{describe_status, MemberIndex},
+        membership::MembershipCache,
+        misc::{date_after, describe_chat},
+        policy::apply_measure,
+        posts::{is_channel_post, is_comment_to_post, ChannelPosts},
+        quarantine::Quarantine,
+        raid::Lockdown,
+        rules::Admission,
+        sender_chats::{allow_keyboard, SenderChats},
+        verified::VerifiedUsers,
+    };
+
+    const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+    const WARNING_LIFETIME: Duration = Duration::from_secs(60);
+
+    pub fn remember_sender() -> HandlerType {
+        dptree::inspect_async(
+            |msg: Message, cfg: Arc<Config>, members: Arc<Storage<MemberIndex>>| async move {
+                //
+                // a message sent on behalf of a chat has a placeholder user as a sender
+                //
+                if msg.sender_chat().is_some() {
+                    return;
+                }
+
+                if msg.chat.id == cfg.channel_chat_id || msg.chat.id == cfg.channel_id {
+                    if let Some(user) = msg.from() {
+                        //
+                        // most messages come from known users, saving the index for each of them isn't worth it
+                        //
+                        if members
+                            .read(|index| index.is_up_to_date(msg.chat.id, user, Utc::now()))
+                            .await
+                        {
+                            return;
+                        }
+
+                        members
+                            .update(|index| index.observe(msg.chat.id, user, None))
+                            .await;
+                    }
+                }
+            },
+        )
+    }
+
+    /// Remembers posts of channel forwarded into its chat, so comments to them are told apart from other replies.
+    pub fn remember_channel_post() -> HandlerType {
+        dptree::inspect_async(
+            |msg: Message, cfg: Arc<Config>, posts: Arc<Storage<ChannelPosts>>| async move {
+                if msg.chat.id == cfg.channel_chat_id && is_channel_post(&msg, cfg.channel_id) {
+                    log::debug!("Post of channel was forwarded into chat as {}", msg.id);
+
+                    posts.update(|posts| posts.add(msg.id.0)).await;
+                }
+            },
+        )
+    }
 
     pub fn user_was_kicked_from_channel_chat() -> HandlerType {
         filter_channel_chat()
@@ -293,17 +985,192 @@ pub mod message {
             )
     }
 
-    #[derive(BotCommands, Clone)]
-    #[command(rename_rule = "lowercase")]
-    enum MaintainerCommands {
-        #[command(description = "get ids")]
-        Ids,
-        #[command(description = "unban maintainer in channel's chat")]
-        UnbanChat,
-        #[command(description = "unban maintainer in channel")]
-        UnbanChannel,
-        #[command(description = "kick (w/ ban) maintainer from channel")]
-        KickAndBan,
+    pub fn unverified_user_sent_message() -> HandlerType {
+        filter_channel_chat()
+            .filter(|cfg: Arc<Config>| cfg.message_policy != MessagePolicy::Off)
+            //
+            // pass only regular messages of users who haven't been checked recently
+            //
+            .filter_map_async(
+                |msg: Message, cfg: Arc<Config>, verified: Arc<VerifiedUsers>, posts: Arc<Storage<ChannelPosts>>| async move {
+                    if msg.sender_chat().is_some() || !matches!(msg.kind, MessageKind::Common(_)) {
+                        return None;
+                    }
+
+                    //
+                    // comments below posts are open for anyone
+                    //
+                    if cfg.message_policy == MessagePolicy::CommentsOnly
+                        && posts.read(|posts| is_comment_to_post(&msg, cfg.channel_id, posts)).await
+                    {
+                        return None;
+                    }
+
+                    let user = msg.from()?.clone();
+                    if verified.is_verified(user.id).await {
+                        return None;
+                    }
+
+                    Some(user)
+                },
+            )
+            .inspect(|| {
+                log::debug!("unverified_user_sent_message: filters passed, calling endpoint");
+            })
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 user: User,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 verified: Arc<VerifiedUsers>,
+                 admission: Arc<Admission>,
+                 admins: Arc<AdminCache>| async move {
+                    let rule = if admins.is_admin(&bot, cfg.channel_chat_id, user.id).await?
+                        || audit.read(|log| log.is_pardoned(user.id)).await
+                    {
+                        None
+                    } else {
+                        let link = audit.read(|log| log.join_link(user.id).cloned()).await;
+
+                        admission.check(&bot, &user, link.as_ref()).await?
+                    };
+
+                    let Some(rule) = rule else {
+                        log::debug!("({:?}, {}) is allowed to write into chat ({})", user.username, user.id, cfg.channel_chat_id);
+
+                        verified.verify(user.id).await;
+
+                        return respond(());
+                    };
+                    log::debug!("({:?}, {}) does NOT match rule {}", user.username, user.id, rule);
+
+                    if cfg.message_policy == MessagePolicy::CommentsOnly {
+                        bot.delete_message(msg.chat.id, msg.id).await?;
+                        log::debug!("message of ({:?}, {}) beyond comments deleted", user.username, user.id);
+
+                        if cfg.warn_about_deleted_messages {
+                            let warning = bot
+                                .send_message(
+                                    msg.chat.id,
+                                    format!(
+                                        "{}, обсуждения вне комментариев к постам доступны только подписчикам канала",
+                                        describe_user(&user)
+                                    ),
+                                )
+                                .await?;
+                            log::debug!("({:?}, {}) has been warned", user.username, user.id);
+
+                            //
+                            // warning isn't needed for long, so it's removed to keep chat clean
+                            //
+                            tokio::spawn(async move {
+                                tokio::time::sleep(WARNING_LIFETIME).await;
+
+                                if let Err(err) = bot.delete_message(warning.chat.id, warning.id).await {
+                                    log::error!("Failed to delete warning: {}", err);
+                                }
+                            });
+                        }
+
+                        return respond(());
+                    }
+
+                    if cfg.delete_offending_messages {
+                        bot.delete_message(msg.chat.id, msg.id).await?;
+                        log::debug!("message of ({:?}, {}) deleted", user.username, user.id);
+                    }
+
+                    let action = apply_measure(&bot, cfg.channel_chat_id, user.id, cfg.action_on_message, None).await?;
+
+                    report(
+                        &bot,
+                        &cfg,
+                        &audit,
+                        &user,
+                        action,
+                        format!("{} не прошел(а) проверку {} и написал(а) в чат", describe_user(&user), rule),
+                    )
+                    .await?;
+
+                    respond(())
+                },
+            )
+    }
+
+    pub fn user_sent_message_on_behalf_of_chat() -> HandlerType {
+        filter_channel_chat()
+            //
+            // channel's posts are forwarded automatically and anonymous admins write on behalf of chat itself
+            //
+            .filter_map(|msg: Message, cfg: Arc<Config>| {
+                msg.sender_chat()
+                    .filter(|chat| chat.id != cfg.channel_id && chat.id != cfg.channel_chat_id)
+                    .cloned()
+            })
+            .filter_async(
+                |sender_chat: Chat, cfg: Arc<Config>, sender_chats: Arc<Storage<SenderChats>>| async move {
+                    !cfg.allowed_sender_chats.contains(&sender_chat.id)
+                        && !sender_chats.read(|chats| chats.is_allowed(sender_chat.id)).await
+                },
+            )
+            .inspect(|| {
+                log::debug!("user_sent_message_on_behalf_of_chat: filters passed, calling endpoint");
+            })
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 sender_chat: Chat,
+                 cfg: Arc<Config>,
+                 sender_chats: Arc<Storage<SenderChats>>| async move {
+                    let mut message = format!("{} пишет в чат от имени канала", describe_chat(&sender_chat));
+
+                    if cfg.sender_chat_policy != SenderChatPolicy::Allow {
+                        bot.delete_message(msg.chat.id, msg.id).await?;
+                        log::debug!("message on behalf of ({}) deleted", sender_chat.id);
+
+                        message.push_str(", сообщение удалено");
+                    }
+
+                    if cfg.sender_chat_policy == SenderChatPolicy::Ban {
+                        bot.ban_chat_sender_chat(msg.chat.id, sender_chat.id).await?;
+                        log::debug!("({}) has been banned in chat ({})", sender_chat.id, msg.chat.id);
+
+                        message.push_str(", канал заблокирован");
+                    }
+
+                    //
+                    // work chat is notified once in a while per sender chat to not flood it
+                    //
+                    let now = Utc::now();
+                    if sender_chats.read(|chats| chats.is_report_due(sender_chat.id, now)).await {
+                        sender_chats.update(|chats| chats.report(sender_chat.id, now)).await;
+
+                        let mut request = bot.send_message(cfg.work_chat_id, message);
+                        if cfg.sender_chat_policy != SenderChatPolicy::Allow {
+                            request = request.reply_markup(allow_keyboard(sender_chat.id));
+                        }
+
+                        request.await?;
+                        log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+                    }
+
+                    respond(())
+                },
+            )
+    }
+
+    #[derive(BotCommands, Clone)]
+    #[command(rename_rule = "lowercase")]
+    enum MaintainerCommands {
+        #[command(description = "get ids")]
+        Ids,
+        #[command(description = "unban maintainer in channel's chat")]
+        UnbanChat,
+        #[command(description = "unban maintainer in channel")]
+        UnbanChannel,
+        #[command(description = "kick (w/ ban) maintainer from channel")]
+        KickAndBan,
         #[command(description = "kick (w/o ban) maintainer from channel")]
         Kick,
         #[command(description = "show this message")]
@@ -370,6 +1237,415 @@ pub mod message {
         )
     }
 
+    #[derive(BotCommands, Clone)]
+    #[command(rename_rule = "lowercase")]
+    enum WorkChatCommands {
+        #[command(description = "undo Bot's action by its id")]
+        Undo(u64),
+        #[command(description = "check all known members of channel's chat against channel")]
+        Reconcile,
+        #[command(description = "show what Bot knows about a user, by id or @username")]
+        Member(String),
+        #[command(description = "show number of known users by their status")]
+        Members,
+        #[command(description = "allow writing on behalf of a chat by its id")]
+        AllowSender(i64),
+        #[command(description = "disallow writing on behalf of a chat by its id")]
+        DisallowSender(i64),
+        #[command(description = "show active bans issued by Bot")]
+        Bans,
+        #[command(description = "forget how many times a user failed the check, by id")]
+        ResetStrikes(u64),
+        #[command(description = "check a user whose removal was undone like anyone else, by id")]
+        Unpardon(u64),
+        #[command(
+            description = "show how many users every admin added for the last days (7 by default)"
+        )]
+        Invites(String),
+        #[command(description = "end lockdown of channel's chat")]
+        Unlock,
+        #[command(description = "show how well Bot's caches work")]
+        Caches,
+    }
+
+    /// Adds the chat to allowed ones and lifts its ban if it was banned by Bot.
+    pub async fn allow_sender_chat(
+        bot: &Bot,
+        cfg: &Config,
+        sender_chats: &Storage<SenderChats>,
+        id: ChatId,
+    ) -> Result<String, RequestError> {
+        if !sender_chats.update(|chats| chats.allow(id)).await {
+            return Ok(format!("Писать от имени {} уже разрешено", id));
+        }
+
+        if cfg.sender_chat_policy == SenderChatPolicy::Ban {
+            bot.unban_chat_sender_chat(cfg.channel_chat_id, id).await?;
+            log::debug!(
+                "({}) has been unbanned in chat ({})",
+                id,
+                cfg.channel_chat_id
+            );
+        }
+
+        Ok(format!("Писать от имени {} разрешено", id))
+    }
+
+    pub fn sent_work_chat_command() -> HandlerType {
+        dptree::filter(|msg: Message, cfg: Arc<Config>| msg.chat.id == cfg.work_chat_id)
+            .filter_command::<WorkChatCommands>()
+            .inspect(|| {
+                log::debug!("sent_work_chat_command: filters passed, calling endpoint");
+            })
+            //
+            // these commands need other dependencies than the rest, and an endpoint can't take all of them
+            //
+            .branch(dptree::case![WorkChatCommands::Unlock].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 verified: Arc<VerifiedUsers>,
+                 admission: Arc<Admission>,
+                 quarantine: Arc<Storage<Quarantine>>,
+                 lockdown: Arc<Storage<Lockdown>>| async move {
+                    if !lockdown.read(Lockdown::is_active).await {
+                        bot.send_message(msg.chat.id, "Режим блокировки не включен")
+                            .reply_to_message_id(msg.id)
+                            .await?;
+
+                        return respond(());
+                    }
+
+                    //
+                    // users who joined during the lockdown are checked one by one, so it's run in background
+                    //
+                    tokio::spawn(async move {
+                        if let Err(err) =
+                            end_lockdown(&bot, &cfg, &audit, &verified, &admission, &quarantine, &lockdown).await
+                        {
+                            log::error!("Failed to end lockdown: {}", err);
+                        }
+                    });
+
+                    respond(())
+                },
+            ))
+            .branch(dptree::case![WorkChatCommands::Reconcile].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 cfg: Arc<Config>,
+                 ids: Arc<Mutex<HashSet<UserId>>>,
+                 audit: Arc<Storage<AuditLog>>,
+                 members: Arc<Storage<MemberIndex>>,
+                 reconciliation: Arc<Reconciliation>| async move {
+                    let Some(running) = reconciliation.start() else {
+                        bot.send_message(msg.chat.id, "Сверка уже идет, дождитесь ее итогов")
+                            .reply_to_message_id(msg.id)
+                            .await?;
+
+                        return respond(());
+                    };
+
+                    //
+                    // it takes a while, so it's run in background to not block other updates
+                    //
+                    tokio::spawn(async move {
+                        let _running = running;
+
+                        if let Err(err) = reconcile(&bot, &cfg, &members, &audit, &ids).await {
+                            log::error!("Reconciliation failed: {}", err);
+                        }
+                    });
+
+                    respond(())
+                },
+            ))
+            .branch(dptree::case![WorkChatCommands::Caches].endpoint(
+                |bot: Bot, msg: Message, admins: Arc<AdminCache>, membership: Arc<MembershipCache>| async move {
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("Кэши:\n- {}\n- {}", admins.describe_stats(), membership.describe_stats()),
+                    )
+                    .reply_to_message_id(msg.id)
+                    .await?;
+
+                    respond(())
+                },
+            ))
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 cmd: WorkChatCommands,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 members: Arc<Storage<MemberIndex>>,
+                 sender_chats: Arc<Storage<SenderChats>>,
+                 invites: Arc<Storage<Invites>>| async move {
+                    match cmd {
+                        WorkChatCommands::Undo(id) => {
+                            let outcome = undo(&bot, &audit, id, date_after(cfg.pardon_ttl)).await?;
+
+                            bot.send_message(msg.chat.id, outcome.describe(id))
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::Member(query) => {
+                            let message = members
+                                .read(|index| {
+                                    let chats = [("чат", cfg.channel_chat_id), ("канал", cfg.channel_id)];
+
+                                    let user_id = match query.trim().parse() {
+                                        Ok(id) => UserId(id),
+                                        Err(_) => chats
+                                            .iter()
+                                            .find_map(|&(_, chat_id)| index.find_by_username(chat_id, query.trim()))
+                                            .map(|(user_id, _)| user_id)?,
+                                    };
+
+                                    let mut message = format!("Пользователь {}", user_id);
+                                    for (title, chat_id) in chats {
+                                        match index.get(chat_id, user_id) {
+                                            Some(member) => message.push_str(&format!(
+                                                "\n- {}: {}, статус {}, впервые замечен {}, последний раз {}",
+                                                title,
+                                                member.describe(),
+                                                describe_status(member.status),
+                                                member.first_seen.format(DATE_FORMAT),
+                                                member.last_seen.format(DATE_FORMAT)
+                                            )),
+                                            None => message.push_str(&format!("\n- {}: не замечен", title)),
+                                        }
+                                    }
+
+                                    Some(message)
+                                })
+                                .await
+                                .unwrap_or_else(|| format!("Пользователь {} не найден", query));
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::AllowSender(id) => {
+                            let result = allow_sender_chat(&bot, &cfg, &sender_chats, ChatId(id)).await?;
+
+                            bot.send_message(msg.chat.id, result)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::DisallowSender(id) => {
+                            let result = if sender_chats.update(|chats| chats.disallow(ChatId(id))).await {
+                                format!("Писать от имени {} больше нельзя", id)
+                            } else if cfg.allowed_sender_chats.contains(&ChatId(id)) {
+                                format!("{} разрешен в настройках Бота", id)
+                            } else {
+                                format!("{} не был разрешен", id)
+                            };
+
+                            bot.send_message(msg.chat.id, result)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::Members => {
+                            let message = members
+                                .read(|index| {
+                                    let mut message = String::from("Известные пользователи:");
+                                    for (title, chat_id) in [("чат", cfg.channel_chat_id), ("канал", cfg.channel_id)] {
+                                        let counts = index
+                                            .count_by_status(chat_id)
+                                            .into_iter()
+                                            .map(|(status, count)| format!("{} {}", status, count))
+                                            .collect::<Vec<_>>();
+
+                                        message.push_str(&format!("\n- {}: {}", title, counts.join(", ")));
+                                    }
+
+                                    message
+                                })
+                                .await;
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::Invites(days) => {
+                            let message = match days.trim() {
+                                "" => Ok(INVITES_REPORT_DAYS),
+                                days => days.parse::<u64>(),
+                            };
+                            let message = match message {
+                                Ok(days) => {
+                                    let since = Utc::now() - chrono::Duration::days(days as i64);
+                                    let summary = invites.read(|invites| invites.summary(since)).await;
+
+                                    describe_summary(&summary, days)
+                                }
+                                Err(_) => format!("Не удалось разобрать число дней: {}", days),
+                            };
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::ResetStrikes(id) => {
+                            let message = if audit.update(|log| log.reset_strikes(UserId(id))).await {
+                                format!("Счетчик нарушений пользователя {} сброшен", id)
+                            } else {
+                                format!("У пользователя {} нет нарушений", id)
+                            };
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        //
+                        // handled by their own branches above
+                        //
+                        WorkChatCommands::Unlock | WorkChatCommands::Caches | WorkChatCommands::Reconcile => {}
+                        WorkChatCommands::Unpardon(id) => {
+                            let message = if audit.update(|log| log.revoke_pardon(UserId(id))).await {
+                                format!("Пользователь {} снова будет проходить проверку", id)
+                            } else {
+                                format!("Пользователь {} и так проходит проверку", id)
+                            };
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                        WorkChatCommands::Bans => {
+                            let message = audit
+                                .read(|log| {
+                                    let bans = log.active_bans();
+                                    if bans.is_empty() {
+                                        return "Действующих блокировок нет".to_owned();
+                                    }
+
+                                    let now = Utc::now();
+                                    let mut message = String::from("Действующие блокировки:");
+                                    for entry in bans {
+                                        let term = match entry.action {
+                                            Action::TempBan { until } => format!(
+                                                "до {}, осталось {}",
+                                                until.format(DATE_FORMAT),
+                                                describe_duration(until - now)
+                                            ),
+                                            _ => "бессрочно".to_owned(),
+                                        };
+
+                                        message.push_str(&format!("\n- {}: {} (/undo {})", entry.user, term, entry.id));
+                                        if let Some(link) = &entry.invite_link {
+                                            message.push_str(&format!(", вступил(а) по ссылке {}", link));
+                                        }
+                                    }
+
+                                    message
+                                })
+                                .await;
+
+                            bot.send_message(msg.chat.id, message)
+                                .reply_to_message_id(msg.id)
+                                .await?;
+                        }
+                    }
+
+                    respond(())
+                },
+            )
+    }
+
+    #[derive(BotCommands, Clone)]
+    #[command(rename_rule = "lowercase")]
+    enum InviteCommands {
+        #[command(description = "get a single-use link to channel's chat, for subscribers only")]
+        Invite,
+    }
+
+    /// Gives a subscriber of channel a single-use link to its chat.
+    pub fn sent_invite_command() -> HandlerType {
+        dptree::filter(|msg: Message, cfg: Arc<Config>| {
+            msg.chat.is_private() && cfg.personal_invites
+        })
+        .filter_command::<InviteCommands>()
+        .inspect(|| {
+            log::debug!("sent_invite_command: filters passed, calling endpoint");
+        })
+        .endpoint(
+            |bot: Bot, msg: Message, cfg: Arc<Config>, invites: Arc<Storage<Invites>>| async move {
+                let Some(user) = msg.from() else {
+                    return respond(());
+                };
+
+                //
+                // a link given before is still valid, but the user may have unsubscribed since then
+                //
+                let reply = if !bot
+                    .get_chat_member(cfg.channel_id, user.id)
+                    .await?
+                    .is_present()
+                {
+                    log::debug!(
+                        "({:?}, {}) asked for a personal link, but isn't a subscriber",
+                        user.username,
+                        user.id
+                    );
+
+                    "Ссылка в чат выдается только подписчикам канала".to_owned()
+                } else if let Some(link) = invites
+                    .read(|invites| invites.personal_link_of(user.id, Utc::now()).cloned())
+                    .await
+                {
+                    format!(
+                        "Ваша ссылка в чат: {}\nОна действует до {}",
+                        link.url,
+                        link.expires.format(DATE_FORMAT)
+                    )
+                } else if bot
+                    .get_chat_member(cfg.channel_chat_id, user.id)
+                    .await?
+                    .is_present()
+                {
+                    "Вы уже состоите в чате".to_owned()
+                } else {
+                    let expires = date_after(cfg.personal_invite_ttl);
+                    let link = bot
+                        .create_chat_invite_link(cfg.channel_chat_id)
+                        .name(format!("Личная ссылка {}", user.id))
+                        .member_limit(1)
+                        .expire_date(expires)
+                        .await?;
+                    log::debug!(
+                        "Personal link was created for ({:?}, {})",
+                        user.username,
+                        user.id
+                    );
+
+                    invites
+                        .update(|invites| {
+                            invites.add_personal_link(PersonalLink {
+                                url: link.invite_link.clone(),
+                                user_id: user.id,
+                                user: describe_user(user),
+                                expires,
+                            })
+                        })
+                        .await;
+
+                    format!(
+                        "Ваша ссылка в чат: {}\nЕй можно воспользоваться один раз до {}",
+                        link.invite_link,
+                        expires.format(DATE_FORMAT)
+                    )
+                };
+
+                bot.send_message(msg.chat.id, reply).await?;
+
+                respond(())
+            },
+        )
+    }
+
     #[derive(BotCommands, Clone)]
     #[command(rename_rule = "lowercase")]
     enum CheckhealthCommands {
@@ -390,3 +1666,285 @@ pub mod message {
             )
     }
 }
+
+pub mod callback {
+    use super::*;
+    use std::str::FromStr;
+    use teloxide::{
+        dptree,
+        payloads::AnswerCallbackQuerySetters,
+        types::{CallbackQuery, ChatId, Message},
+    };
+
+    use crate::{
+        audit::UNDO_CALLBACK_PREFIX,
+        captcha::{clean_up, Answer, CAPTCHA_CALLBACK_PREFIX},
+        config::InviteEnforcement,
+        handlers::message::allow_sender_chat,
+        invites::{Invites, KEEP_INVITED_CALLBACK_PREFIX},
+        misc::{create_username_or_default, date_after},
+        policy::{apply_measure, Measure},
+        quarantine::{release, Quarantine},
+        sender_chats::{SenderChats, ALLOW_SENDER_CHAT_CALLBACK_PREFIX},
+        verified::VerifiedUsers,
+    };
+
+    /// Passes only buttons under messages in work chat which data starts with the prefix.
+    /// The rest of data is parsed and passed further.
+    fn filter_work_chat_button<T>(prefix: &'static str) -> HandlerType
+    where
+        T: FromStr + Send + Sync + 'static,
+    {
+        dptree::filter_map(move |query: CallbackQuery, cfg: Arc<Config>| {
+            if query
+                .message
+                .is_some_and(|msg| msg.chat.id == cfg.work_chat_id)
+            {
+                //
+                // fields after the first one only tell which user the button is about, see misc::affected_user
+                //
+                return query
+                    .data?
+                    .strip_prefix(prefix)?
+                    .split(':')
+                    .next()?
+                    .parse::<T>()
+                    .ok();
+            }
+
+            None
+        })
+    }
+
+    /// Shows the result to the admin who pressed the button and adds it to the message with the button.
+    async fn show_result(
+        bot: &Bot,
+        query: &CallbackQuery,
+        result: &str,
+    ) -> Result<(), RequestError> {
+        bot.answer_callback_query(&query.id).text(result).await?;
+
+        if let Some(Message {
+            chat,
+            id: message_id,
+            ..
+        }) = &query.message
+        {
+            //
+            // edited message has no keyboard, so the button can't be pressed twice
+            //
+            bot.edit_message_text(
+                chat.id,
+                *message_id,
+                format!(
+                    "{}\n\n{} ({})",
+                    query
+                        .message
+                        .as_ref()
+                        .and_then(Message::text)
+                        .unwrap_or_default(),
+                    result,
+                    create_username_or_default(
+                        "(админ скрыл свой ID)",
+                        query.from.username.as_ref()
+                    )
+                ),
+            )
+            .await?;
+            log::debug!(
+                "Result was added to message ({}) in chat ({})",
+                message_id,
+                chat.id
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn admin_pressed_undo_button() -> HandlerType {
+        filter_work_chat_button::<u64>(UNDO_CALLBACK_PREFIX)
+            .inspect(|| {
+                log::debug!("admin_pressed_undo_button: filters passed, calling endpoint");
+            })
+            .endpoint(
+                |bot: Bot,
+                 query: CallbackQuery,
+                 id: u64,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>| async move {
+                    let outcome = undo(&bot, &audit, id, date_after(cfg.pardon_ttl)).await?;
+                    show_result(&bot, &query, &outcome.describe(id)).await?;
+
+                    respond(())
+                },
+            )
+    }
+
+    pub fn admin_pressed_allow_sender_chat_button() -> HandlerType {
+        filter_work_chat_button::<i64>(ALLOW_SENDER_CHAT_CALLBACK_PREFIX)
+            .inspect(|| {
+                log::debug!(
+                    "admin_pressed_allow_sender_chat_button: filters passed, calling endpoint"
+                );
+            })
+            .endpoint(
+                |bot: Bot,
+                 query: CallbackQuery,
+                 id: i64,
+                 cfg: Arc<Config>,
+                 sender_chats: Arc<Storage<SenderChats>>| async move {
+                    let result = allow_sender_chat(&bot, &cfg, &sender_chats, ChatId(id)).await?;
+                    show_result(&bot, &query, &result).await?;
+
+                    respond(())
+                },
+            )
+    }
+
+    pub fn user_pressed_captcha_button() -> HandlerType {
+        dptree::filter_map(|query: CallbackQuery, cfg: Arc<Config>| {
+            if query
+                .message
+                .is_some_and(|msg| msg.chat.id == cfg.channel_chat_id)
+            {
+                return query
+                    .data?
+                    .strip_prefix(CAPTCHA_CALLBACK_PREFIX)?
+                    .parse::<Answer>()
+                    .ok();
+            }
+
+            None
+        })
+        .inspect(|| {
+            log::debug!("user_pressed_captcha_button: filters passed, calling endpoint");
+        })
+        .endpoint(
+            |bot: Bot,
+             query: CallbackQuery,
+             answer: Answer,
+             cfg: Arc<Config>,
+             audit: Arc<Storage<AuditLog>>,
+             verified: Arc<VerifiedUsers>,
+             quarantine: Arc<Storage<Quarantine>>| async move {
+                if query.from.id != answer.user_id {
+                    bot.answer_callback_query(&query.id)
+                        .text("Этот вопрос задан не вам")
+                        .await?;
+
+                    return respond(());
+                }
+
+                let Some(challenge) = quarantine
+                    .update(|quarantine| quarantine.take_challenge(answer.user_id))
+                    .await
+                else {
+                    bot.answer_callback_query(&query.id)
+                        .text("Время на ответ истекло")
+                        .await?;
+
+                    return respond(());
+                };
+                clean_up(&bot, &cfg, &challenge).await;
+
+                let user = challenge.user;
+                if answer.option == challenge.answer {
+                    log::debug!("({:?}, {}) answered captcha", user.username, user.id);
+
+                    verified.verify(user.id).await;
+                    release(&bot, cfg.channel_chat_id, user.id, &quarantine).await?;
+
+                    bot.answer_callback_query(&query.id)
+                        .text("Верно, теперь вы можете писать в чат")
+                        .await?;
+                    report(
+                        &bot,
+                        &cfg,
+                        &audit,
+                        &user,
+                        None,
+                        format!("{} ответил(а) на капчу", describe_user(&user)),
+                    )
+                    .await?;
+                } else {
+                    log::debug!(
+                        "({:?}, {}) gave a wrong answer to captcha",
+                        user.username,
+                        user.id
+                    );
+
+                    bot.answer_callback_query(&query.id).text("Неверно").await?;
+
+                    let action =
+                        apply_measure(&bot, cfg.channel_chat_id, user.id, Measure::Kick, None)
+                            .await?;
+                    quarantine
+                        .update(|quarantine| quarantine.remove(user.id))
+                        .await;
+                    report(
+                        &bot,
+                        &cfg,
+                        &audit,
+                        &user,
+                        action,
+                        format!("{} неверно ответил(а) на капчу", describe_user(&user)),
+                    )
+                    .await?;
+                }
+
+                respond(())
+            },
+        )
+    }
+
+    pub fn admin_pressed_keep_invited_button() -> HandlerType {
+        filter_work_chat_button::<u64>(KEEP_INVITED_CALLBACK_PREFIX)
+            .inspect(|| {
+                log::debug!("admin_pressed_keep_invited_button: filters passed, calling endpoint");
+            })
+            .endpoint(
+                |bot: Bot,
+                 query: CallbackQuery,
+                 id: u64,
+                 cfg: Arc<Config>,
+                 audit: Arc<Storage<AuditLog>>,
+                 verified: Arc<VerifiedUsers>,
+                 invites: Arc<Storage<Invites>>| async move {
+                    let user_id = UserId(id);
+                    let Some(invite) = invites.read(|invites| invites.get(user_id).cloned()).await
+                    else {
+                        show_result(&bot, &query, "Решение уже принято").await?;
+
+                        return respond(());
+                    };
+
+                    let inviter = query.from.id == invite.admin.id;
+                    let refusal = match cfg.invite_enforcement {
+                        InviteEnforcement::Acknowledge if !inviter => {
+                            Some("Оставить пользователя может только добавивший его админ")
+                        }
+                        InviteEnforcement::Confirm if inviter => {
+                            Some("Оставить пользователя должен другой админ")
+                        }
+                        _ => None,
+                    };
+                    if let Some(refusal) = refusal {
+                        bot.answer_callback_query(&query.id).text(refusal).await?;
+
+                        return respond(());
+                    }
+
+                    invites.update(|invites| invites.remove(user_id)).await;
+                    audit
+                        .update(|log| log.pardon(user_id, date_after(cfg.pardon_ttl)))
+                        .await;
+                    verified.verify(user_id).await;
+                    log::debug!("{} is kept in chat by ({})", user_id, query.from.id);
+
+                    show_result(&bot, &query, "Пользователь оставлен в чате").await?;
+
+                    respond(())
+                },
+            )
+    }
+}
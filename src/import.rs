@@ -0,0 +1,107 @@
+use std::{collections::HashSet, error::Error, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use teloxide::types::{ChatId, ChatMemberStatus, UserId};
+
+use crate::{members::MemberIndex, storage::Storage};
+
+/// Chat history exported by Telegram Desktop (result.json). Only fields Bot is interested in are listed.
+#[derive(Deserialize)]
+struct Export {
+    name: Option<String>,
+    messages: Vec<ExportedMessage>,
+}
+
+#[derive(Deserialize)]
+struct ExportedMessage {
+    date_unixtime: Option<String>,
+    from: Option<String>,
+    from_id: Option<String>,
+    actor: Option<String>,
+    actor_id: Option<String>,
+    action: Option<String>,
+    #[serde(default)]
+    members: Vec<Option<String>>,
+}
+
+impl ExportedMessage {
+    fn date(&self) -> Option<DateTime<Utc>> {
+        let timestamp = self.date_unixtime.as_ref()?.parse().ok()?;
+
+        DateTime::from_timestamp(timestamp, 0)
+    }
+
+    /// A user behind the message and what's known about their status after it.
+    fn user(&self) -> Option<(UserId, &str, Option<ChatMemberStatus>)> {
+        //
+        // service messages have an actor, regular ones have a sender
+        //
+        if let Some(actor_id) = &self.actor_id {
+            let name = self.actor.as_deref().unwrap_or_default();
+            let status = match self.action.as_deref() {
+                Some("join_group_by_link" | "join_group_by_request") => {
+                    Some(ChatMemberStatus::Member)
+                }
+                //
+                // a user who left the chat by themselves is the actor and the only removed member
+                //
+                Some("remove_members")
+                    if self.members.len() == 1 && self.members[0].as_deref() == Some(name) =>
+                {
+                    Some(ChatMemberStatus::Left)
+                }
+                _ => None,
+            };
+
+            return Some((parse_user_id(actor_id)?, name, status));
+        }
+
+        let from_id = self.from_id.as_ref()?;
+        let name = self.from.as_deref().unwrap_or_default();
+
+        Some((parse_user_id(from_id)?, name, None))
+    }
+}
+
+/// Ids of users look like "user123", while chats have ids like "channel123".
+fn parse_user_id(id: &str) -> Option<UserId> {
+    Some(UserId(id.strip_prefix("user")?.parse().ok()?))
+}
+
+/// Loads users found in an exported chat history into the member index of the given chat.
+/// Returns a number of found users.
+pub async fn import(
+    path: &Path,
+    chat_id: ChatId,
+    members: &Storage<MemberIndex>,
+) -> Result<usize, Box<dyn Error>> {
+    let export: Export = serde_json::from_slice(&fs::read(path)?)?;
+    log::info!(
+        "{} messages of {:?} are going to be imported into chat ({})",
+        export.messages.len(),
+        export.name,
+        chat_id
+    );
+
+    let imported = members
+        .update(|index| {
+            let mut users = HashSet::new();
+
+            for message in &export.messages {
+                let (Some((user_id, name, status)), Some(date)) = (message.user(), message.date())
+                else {
+                    continue;
+                };
+
+                index.import(chat_id, user_id, name, date, status);
+                users.insert(user_id);
+            }
+
+            users.len()
+        })
+        .await;
+    log::info!("{} users were imported into chat ({})", imported, chat_id);
+
+    Ok(imported)
+}
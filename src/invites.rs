@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, User, UserId};
+
+pub const KEEP_INVITED_CALLBACK_PREFIX: &str = "keepinvited:";
+
+/// How long invite events are kept.
+const HISTORY_LIFETIME_DAYS: i64 = 365;
+
+/// A user added by an admin who failed the check. They are removed at the deadline unless it's confirmed they can stay.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingInvite {
+    pub user: User,
+    pub admin: User,
+    /// Notification in work chat with the button to keep the user.
+    pub message_id: MessageId,
+    pub text: String,
+    pub deadline: DateTime<Utc>,
+    /// How many times the user failed the check when they were added, it sets the measure taken at the deadline.
+    #[serde(default)]
+    pub strikes: usize,
+}
+
+/// A user added to chat by an admin.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InviteEvent {
+    pub date: DateTime<Utc>,
+    pub admin_id: UserId,
+    /// Admin's name as it was at the moment.
+    pub admin: String,
+    pub user_id: UserId,
+    /// Whether the user passed the check.
+    pub passed: bool,
+}
+
+/// Single-use link to chat Bot created for a subscriber on their request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersonalLink {
+    pub url: String,
+    pub user_id: UserId,
+    /// User's name as it was at the moment.
+    pub user: String,
+    pub expires: DateTime<Utc>,
+}
+
+/// Per admin numbers of added users.
+#[derive(Default)]
+pub struct AdminInvites {
+    pub admin: String,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Users added to chat by admins.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Invites {
+    /// Users who failed the check and wait for confirmation.
+    #[serde(alias = "invites")]
+    pending: BTreeMap<UserId, PendingInvite>,
+    #[serde(default)]
+    history: Vec<InviteEvent>,
+    /// Personal links by URLs. They are revoked once used or expired.
+    #[serde(default)]
+    personal: BTreeMap<String, PersonalLink>,
+    /// Date the last regular report was sent, or the reports were turned on, so restarts don't postpone them.
+    #[serde(default)]
+    last_report: Option<DateTime<Utc>>,
+}
+
+impl Invites {
+    pub fn add(&mut self, invite: PendingInvite) {
+        self.pending.insert(invite.user.id, invite);
+    }
+
+    pub fn get(&self, user_id: UserId) -> Option<&PendingInvite> {
+        self.pending.get(&user_id)
+    }
+
+    pub fn remove(&mut self, user_id: UserId) -> Option<PendingInvite> {
+        self.pending.remove(&user_id)
+    }
+
+    /// Invites which weren't confirmed in time.
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<UserId> {
+        self.pending
+            .values()
+            .filter(|invite| invite.deadline <= now)
+            .map(|invite| invite.user.id)
+            .collect()
+    }
+
+    /// Remembers the event. Events older than [`HISTORY_LIFETIME_DAYS`] are forgotten.
+    pub fn record(&mut self, event: InviteEvent) {
+        let since = event.date - chrono::Duration::days(HISTORY_LIFETIME_DAYS);
+
+        self.history.retain(|event| event.date > since);
+        self.history.push(event);
+    }
+
+    pub fn add_personal_link(&mut self, link: PersonalLink) {
+        self.personal.insert(link.url.clone(), link);
+    }
+
+    /// Link given to the user which is still valid.
+    pub fn personal_link_of(&self, user_id: UserId, now: DateTime<Utc>) -> Option<&PersonalLink> {
+        self.personal
+            .values()
+            .find(|link| link.user_id == user_id && link.expires > now)
+    }
+
+    pub fn take_personal_link(&mut self, url: &str) -> Option<PersonalLink> {
+        self.personal.remove(url)
+    }
+
+    /// URLs of personal links which weren't used in time.
+    pub fn expired_personal_links(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.personal
+            .values()
+            .filter(|link| link.expires <= now)
+            .map(|link| link.url.clone())
+            .collect()
+    }
+
+    pub fn last_report(&self) -> Option<DateTime<Utc>> {
+        self.last_report
+    }
+
+    pub fn set_last_report(&mut self, date: DateTime<Utc>) {
+        self.last_report = Some(date);
+    }
+
+    /// Numbers of users added by every admin since the date, those who added most failed users go first.
+    pub fn summary(&self, since: DateTime<Utc>) -> Vec<AdminInvites> {
+        let mut admins = HashMap::<UserId, AdminInvites>::new();
+
+        for event in self.history.iter().filter(|event| event.date >= since) {
+            let admin = admins.entry(event.admin_id).or_default();
+
+            //
+            // the latest name is shown
+            //
+            admin.admin.clone_from(&event.admin);
+            if event.passed {
+                admin.passed += 1;
+            } else {
+                admin.failed += 1;
+            }
+        }
+
+        let mut summary = admins.into_values().collect::<Vec<_>>();
+        summary.sort_by(|a, b| b.failed.cmp(&a.failed).then(b.passed.cmp(&a.passed)));
+
+        summary
+    }
+}
+
+/// Summary in the way it's shown in work chat.
+pub fn describe_summary(summary: &[AdminInvites], days: u64) -> String {
+    if summary.is_empty() {
+        return format!("За {} д админы никого не добавляли в чат", days);
+    }
+
+    let mut message = format!("Добавленные админами пользователи за {} д:", days);
+    for admin in summary {
+        message.push_str(&format!(
+            "\n- {}: прошли проверку {}, не прошли {}",
+            admin.admin, admin.passed, admin.failed
+        ));
+    }
+
+    message
+}
+
+pub fn keep_invited_keyboard(user_id: UserId) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Оставить в чате",
+        format!("{}{}", KEEP_INVITED_CALLBACK_PREFIX, user_id),
+    )]])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use teloxide::types::UserId;
+
+    use super::{InviteEvent, Invites};
+
+    fn event(date: DateTime<Utc>, admin_id: u64, admin: &str, passed: bool) -> InviteEvent {
+        InviteEvent {
+            date,
+            admin_id: UserId(admin_id),
+            admin: admin.to_owned(),
+            user_id: UserId(1000),
+            passed,
+        }
+    }
+
+    #[test]
+    fn summary_counts_invites_per_admin() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 10, 0, 0, 0).unwrap();
+        let mut invites = Invites::default();
+
+        invites.record(event(now - Duration::days(3), 1, "Anna", true));
+        invites.record(event(now - Duration::days(2), 2, "Boris", false));
+        invites.record(event(now - Duration::days(2), 1, "Anna", true));
+        invites.record(event(now - Duration::days(1), 2, "Boris", true));
+        invites.record(event(now - Duration::days(1), 1, "Anna K.", false));
+
+        let summary = invites.summary(now - Duration::days(7));
+        let summary = summary
+            .iter()
+            .map(|admin| (admin.admin.as_str(), admin.passed, admin.failed))
+            .collect::<Vec<_>>();
+
+        //
+        // ties in failed users are broken by passed ones, and the latest name is shown
+        //
+        assert_eq!(summary, vec![("Anna K.", 2, 1), ("Boris", 1, 1)]);
+    }
+
+    #[test]
+    fn summary_skips_older_invites() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 10, 0, 0, 0).unwrap();
+        let mut invites = Invites::default();
+
+        invites.record(event(now - Duration::days(10), 1, "Anna", false));
+        invites.record(event(now - Duration::days(1), 2, "Boris", false));
+        invites.record(event(now - Duration::days(1), 2, "Boris", false));
+        invites.record(event(now, 3, "Vera", false));
+
+        let summary = invites.summary(now - Duration::days(7));
+        let summary = summary
+            .iter()
+            .map(|admin| (admin.admin.as_str(), admin.passed, admin.failed))
+            .collect::<Vec<_>>();
+
+        assert_eq!(summary, vec![("Boris", 0, 2), ("Vera", 0, 1)]);
+        assert!(invites.summary(now + Duration::days(1)).is_empty());
+    }
+}
@@ -0,0 +1,788 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use teloxide::{
+    payloads::EditMessageTextSetters, requests::Requester, types::UserId, Bot, RequestError,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    admins::AdminCache,
+    audit::{undo_keyboard, Action, AuditLog},
+    captcha::clean_up,
+    config::{Config, InviteEnforcement},
+    handlers::{report, update::check_newcomer},
+    invites::{describe_summary, Invites},
+    members::MemberIndex,
+    membership::MembershipCache,
+    misc::describe_user,
+    policy::{apply_measure, Measure},
+    quarantine::Quarantine,
+    raid::{unlock, Lockdown},
+    rules::Admission,
+    storage::Storage,
+    verified::VerifiedUsers,
+};
+
+/// How often captchas are checked for being answered in time.
+const CAPTCHA_EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often deadlines of invites waiting for confirmation are checked.
+const INVITE_EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often personal invite links are checked for being expired.
+const PERSONAL_LINK_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Period of the regular report about users added by admins.
+pub const INVITES_REPORT_DAYS: u64 = 7;
+
+/// How often it's checked whether the regular report about invites is due.
+const INVITES_REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often users whose check was interrupted are looked for in quarantine.
+const QUARANTINE_RECOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often the end of a lockdown is checked.
+const LOCKDOWN_EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often expiry of temporary bans is checked.
+const BAN_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs [`reconcile`] periodically, if the period is configured.
+pub fn spawn_reconciliation(
+    bot: Bot,
+    cfg: Arc<Config>,
+    members: Arc<Storage<MemberIndex>>,
+    audit: Arc<Storage<AuditLog>>,
+    ids: Arc<Mutex<HashSet<UserId>>>,
+    reconciliation: Arc<Reconciliation>,
+) {
+    let Some(period) = cfg.reconcile_interval else {
+        log::info!("Reconciliation interval is not set, periodic reconciliation is disabled");
+
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let Some(_running) = reconciliation.start() else {
+                log::info!("Reconciliation is running already, periodic one is skipped");
+
+                continue;
+            };
+
+            if let Err(err) = reconcile(&bot, &cfg, &members, &audit, &ids).await {
+                log::error!("Reconciliation failed: {}", err);
+            }
+        }
+    });
+}
+
+/// Tells whether [`reconcile`] is running, so manual and periodic runs don't overlap.
+#[derive(Default)]
+pub struct Reconciliation(AtomicBool);
+
+impl Reconciliation {
+    /// Returns `None` if reconciliation is running already.
+    /// Otherwise it's considered running until the returned guard is dropped.
+    pub fn start(self: &Arc<Self>) -> Option<ReconciliationGuard> {
+        self.0
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()?;
+
+        Some(ReconciliationGuard(self.clone()))
+    }
+}
+
+pub struct ReconciliationGuard(Arc<Reconciliation>);
+
+impl Drop for ReconciliationGuard {
+    fn drop(&mut self) {
+        self.0 .0.store(false, Ordering::Release);
+    }
+}
+
+/// Checks every known member of channel's chat against channel
+/// and removes those who are not in channel, the same way it's done when a user leaves channel.
+/// If ACTION_ON_CHANNEL_LEAVE only notifies, such users are listed in the summary as they are.
+/// Users are checked one by one with a delay to not hit Telegram's limits.
+/// A summary is sent to work chat once all users are checked.
+pub async fn reconcile(
+    bot: &Bot,
+    cfg: &Config,
+    members: &Storage<MemberIndex>,
+    audit: &Storage<AuditLog>,
+    ids: &Mutex<HashSet<UserId>>,
+) -> Result<(), RequestError> {
+    let user_ids = members
+        .read(|index| index.user_ids(cfg.channel_chat_id))
+        .await;
+    log::info!("Reconciliation of {} users started", user_ids.len());
+
+    let mut found = Vec::new();
+    let mut failed = 0;
+
+    for &user_id in &user_ids {
+        match reconcile_user(bot, cfg, audit, ids, user_id).await {
+            Ok(Some(line)) => found.push(line),
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("Failed to reconcile user ({}): {}", user_id, err);
+
+                failed += 1;
+            }
+        }
+
+        tokio::time::sleep(cfg.reconcile_delay).await;
+    }
+    log::info!(
+        "Reconciliation finished: checked {}, not in channel {}, failed {}",
+        user_ids.len(),
+        found.len(),
+        failed
+    );
+
+    let mut message = format!(
+        "Сверка участников чата с каналом завершена. Проверено: {}, не состоят в канале: {}, ошибок: {}",
+        user_ids.len(),
+        found.len(),
+        failed
+    );
+    for line in found {
+        message.push_str("\n- ");
+        message.push_str(&line);
+    }
+
+    bot.send_message(cfg.work_chat_id, message).await?;
+    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+    Ok(())
+}
+
+async fn reconcile_user(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    ids: &Mutex<HashSet<UserId>>,
+    user_id: UserId,
+) -> Result<Option<String>, RequestError> {
+    //
+    // admins restored these users on purpose
+    //
+    if audit.read(|log| log.is_pardoned(user_id)).await {
+        log::debug!("{} was pardoned, reconciliation skips them", user_id);
+
+        return Ok(None);
+    }
+
+    let chat_member = bot.get_chat_member(cfg.channel_chat_id, user_id).await?;
+    if !chat_member.is_present() || chat_member.is_privileged() {
+        return Ok(None);
+    }
+
+    let channel_member = bot.get_chat_member(cfg.channel_id, user_id).await?;
+    if channel_member.is_present() {
+        return Ok(None);
+    }
+    log::debug!(
+        "{} is in chat ({}), but NOT in channel ({})",
+        user_id,
+        cfg.channel_chat_id,
+        cfg.channel_id
+    );
+
+    //
+    // left chat message is going to be deleted as it's done for users who left channel
+    //
+    ids.lock().await.insert(user_id);
+
+    let name = describe_user(&chat_member.user);
+    match apply_measure(
+        bot,
+        cfg.channel_chat_id,
+        user_id,
+        cfg.action_on_channel_leave,
+        Some(&channel_member.kind),
+    )
+    .await
+    {
+        Ok(Some(action)) => {
+            if !action.removes_from_chat() {
+                ids.lock().await.remove(&user_id);
+            }
+
+            let id = audit
+                .update(|log| log.record(cfg.channel_chat_id, user_id, name.clone(), action))
+                .await;
+            log::debug!("{:?} of {} recorded as #{}", action, user_id, id);
+
+            Ok(Some(format!("{} (/undo {})", name, id)))
+        }
+        //
+        // the user isn't touched, but admins should still know about them
+        //
+        Ok(None) => {
+            ids.lock().await.remove(&user_id);
+
+            Ok(Some(format!("{} (только уведомление)", name)))
+        }
+        Err(err) => {
+            ids.lock().await.remove(&user_id);
+
+            Err(err)
+        }
+    }
+}
+
+/// Runs [`lift_expired_bans`] periodically.
+pub fn spawn_ban_expiry(bot: Bot, cfg: Arc<Config>, audit: Arc<Storage<AuditLog>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BAN_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = lift_expired_bans(&bot, &cfg, &audit).await {
+                log::error!("Failed to notify about lifted bans: {}", err);
+            }
+        }
+    });
+}
+
+/// Telegram lifts temporary bans by itself, so expired ones are only marked as lifted in the log.
+/// Work chat is notified about them if it's configured.
+pub async fn lift_expired_bans(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+) -> Result<(), RequestError> {
+    let now = Utc::now();
+
+    //
+    // the log is saved on every update, so it's updated only if there is something to lift
+    //
+    let expired = audit
+        .read(|log| {
+            log.active_bans()
+                .iter()
+                .any(|entry| matches!(entry.action, Action::TempBan { until } if until <= now))
+        })
+        .await;
+    if !expired {
+        return Ok(());
+    }
+
+    let lifted = audit.update(|log| log.lift_expired_bans(now)).await;
+    log::info!("{} temporary bans have expired", lifted.len());
+
+    if !cfg.notify_about_lifted_bans {
+        return Ok(());
+    }
+
+    let mut message = String::from("Истек срок блокировки:");
+    for entry in lifted {
+        message.push_str(&format!("\n- {} (#{})", entry.user, entry.id));
+    }
+
+    bot.send_message(cfg.work_chat_id, message).await?;
+    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+    Ok(())
+}
+
+/// Checks users left in quarantine when Bot was stopped, so they aren't muted forever.
+/// Users who joined during a lockdown are checked once it's over.
+pub fn spawn_quarantine_recovery(
+    bot: Bot,
+    cfg: Arc<Config>,
+    audit: Arc<Storage<AuditLog>>,
+    verified: Arc<VerifiedUsers>,
+    admission: Arc<Admission>,
+    quarantine: Arc<Storage<Quarantine>>,
+    lockdown: Arc<Storage<Lockdown>>,
+) {
+    tokio::spawn(async move {
+        let started = Utc::now();
+        let mut interval = tokio::time::interval(QUARANTINE_RECOVERY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if lockdown.read(Lockdown::is_active).await {
+                continue;
+            }
+
+            //
+            // users who joined after start are checked right away, so only those stuck for a while are checked again
+            //
+            let grace = chrono::Duration::seconds(QUARANTINE_RECOVERY_INTERVAL.as_secs() as i64);
+            let added_before = (Utc::now() - grace).max(started);
+
+            check_quarantined(
+                &bot,
+                &cfg,
+                &audit,
+                &verified,
+                &admission,
+                &quarantine,
+                added_before,
+            )
+            .await;
+        }
+    });
+}
+
+/// Checks users put in quarantine before the date one by one with a delay to not hit Telegram's limits.
+pub async fn check_quarantined(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    verified: &VerifiedUsers,
+    admission: &Admission,
+    quarantine: &Storage<Quarantine>,
+    added_before: DateTime<Utc>,
+) {
+    let user_ids = quarantine
+        .read(|quarantine| quarantine.unchecked(added_before))
+        .await;
+    if user_ids.is_empty() {
+        return;
+    }
+    log::info!("{} users are in quarantine, checking them", user_ids.len());
+
+    for user_id in user_ids {
+        //
+        // a question may have been asked since the list was taken, then it's up to captcha expiry to deal with the user
+        //
+        if quarantine
+            .read(|quarantine| quarantine.has_challenge(user_id))
+            .await
+        {
+            continue;
+        }
+
+        let result = async {
+            let member = bot.get_chat_member(cfg.channel_chat_id, user_id).await?;
+            if !member.is_present() {
+                log::debug!("{} isn't in chat anymore, quarantine is over", user_id);
+                quarantine
+                    .update(|quarantine| quarantine.remove(user_id))
+                    .await;
+
+                return Ok(());
+            }
+
+            check_newcomer(
+                bot,
+                cfg,
+                audit,
+                verified,
+                admission,
+                quarantine,
+                &member.user,
+            )
+            .await
+        }
+        .await;
+
+        if let Err(err) = result {
+            log::error!("Failed to check user ({}) in quarantine: {}", user_id, err);
+        }
+
+        tokio::time::sleep(cfg.reconcile_delay).await;
+    }
+}
+
+/// Ends the lockdown once its time is over. It runs if raid detection is enabled
+/// or a lockdown was left active before restart, e.g. when detection has been turned off since then.
+pub fn spawn_lockdown_expiry(
+    bot: Bot,
+    cfg: Arc<Config>,
+    audit: Arc<Storage<AuditLog>>,
+    verified: Arc<VerifiedUsers>,
+    admission: Arc<Admission>,
+    quarantine: Arc<Storage<Quarantine>>,
+    lockdown: Arc<Storage<Lockdown>>,
+) {
+    tokio::spawn(async move {
+        if cfg.raid_joins.is_none() && !lockdown.read(Lockdown::is_active).await {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(LOCKDOWN_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if !lockdown
+                .read(|lockdown| lockdown.is_expired(Utc::now()))
+                .await
+            {
+                continue;
+            }
+
+            if let Err(err) = end_lockdown(
+                &bot,
+                &cfg,
+                &audit,
+                &verified,
+                &admission,
+                &quarantine,
+                &lockdown,
+            )
+            .await
+            {
+                log::error!("Failed to end lockdown: {}", err);
+            }
+        }
+    });
+}
+
+/// Ends the lockdown, notifies work chat and checks users who joined during the lockdown.
+pub async fn end_lockdown(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    verified: &VerifiedUsers,
+    admission: &Admission,
+    quarantine: &Storage<Quarantine>,
+    lockdown: &Storage<Lockdown>,
+) -> Result<(), RequestError> {
+    let Some(ended) = unlock(bot, cfg.channel_chat_id, lockdown).await? else {
+        return Ok(());
+    };
+
+    bot.send_message(
+        cfg.work_chat_id,
+        format!(
+            "Режим блокировки чата снят. Вступили за время блокировки: {}, они будут проверены",
+            ended.joined
+        ),
+    )
+    .await?;
+    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+    check_quarantined(bot, cfg, audit, verified, admission, quarantine, Utc::now()).await;
+
+    Ok(())
+}
+
+/// Runs [`remove_users_without_answer`] periodically, if captcha is enabled.
+pub fn spawn_captcha_expiry(
+    bot: Bot,
+    cfg: Arc<Config>,
+    audit: Arc<Storage<AuditLog>>,
+    quarantine: Arc<Storage<Quarantine>>,
+) {
+    if !cfg.captcha {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CAPTCHA_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = remove_users_without_answer(&bot, &cfg, &audit, &quarantine).await {
+                log::error!("Failed to remove users who didn't answer captcha: {}", err);
+            }
+        }
+    });
+}
+
+/// Removes users who didn't answer captcha in time from chat. They are able to join it again.
+pub async fn remove_users_without_answer(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    quarantine: &Storage<Quarantine>,
+) -> Result<(), RequestError> {
+    let user_ids = quarantine
+        .read(|quarantine| quarantine.expired_challenges(Utc::now()))
+        .await;
+
+    for user_id in user_ids {
+        let Some(challenge) = quarantine
+            .update(|quarantine| quarantine.take_challenge(user_id))
+            .await
+        else {
+            continue;
+        };
+        clean_up(bot, cfg, &challenge).await;
+
+        let user = challenge.user;
+        log::debug!(
+            "({:?}, {}) didn't answer captcha in time",
+            user.username,
+            user.id
+        );
+
+        let action = apply_measure(bot, cfg.channel_chat_id, user.id, Measure::Kick, None).await?;
+        quarantine
+            .update(|quarantine| quarantine.remove(user.id))
+            .await;
+        report(
+            bot,
+            cfg,
+            audit,
+            &user,
+            action,
+            format!("{} не ответил(а) на капчу вовремя", describe_user(&user)),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Runs [`remove_unconfirmed_invites`] periodically, if invites should be confirmed.
+pub fn spawn_invite_expiry(
+    bot: Bot,
+    cfg: Arc<Config>,
+    audit: Arc<Storage<AuditLog>>,
+    invites: Arc<Storage<Invites>>,
+) {
+    if cfg.invite_enforcement == InviteEnforcement::Off {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INVITE_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = remove_unconfirmed_invites(&bot, &cfg, &audit, &invites).await {
+                log::error!(
+                    "Failed to remove users whose invites weren't confirmed: {}",
+                    err
+                );
+            }
+        }
+    });
+}
+
+/// Takes the action against users added by admins who weren't kept in chat in time.
+/// The outcome is added to the notification about the user.
+pub async fn remove_unconfirmed_invites(
+    bot: &Bot,
+    cfg: &Config,
+    audit: &Storage<AuditLog>,
+    invites: &Storage<Invites>,
+) -> Result<(), RequestError> {
+    let user_ids = invites.read(|invites| invites.expired(Utc::now())).await;
+
+    for user_id in user_ids {
+        let Some(invite) = invites.read(|invites| invites.get(user_id).cloned()).await else {
+            continue;
+        };
+        log::debug!("Invite of {} wasn't confirmed in time", user_id);
+
+        //
+        // notifying only makes no sense here, so such users are removed
+        //
+        let measure = match cfg.escalate(invite.strikes, cfg.action_on_invite) {
+            Measure::Notify => Measure::Kick,
+            measure => measure,
+        };
+        let action = apply_measure(bot, cfg.channel_chat_id, user_id, measure, None).await?;
+
+        //
+        // invite is kept until the measure is taken, so it's retried if the measure fails
+        //
+        invites.update(|invites| invites.remove(user_id)).await;
+
+        let outcome = match action {
+            Some(action) => format!(
+                "Не подтверждено вовремя, пользователь {}",
+                action.describe()
+            ),
+            None => "Не подтверждено вовремя".to_owned(),
+        };
+        let edit = bot.edit_message_text(
+            cfg.work_chat_id,
+            invite.message_id,
+            format!("{}\n\n{}", invite.text, outcome),
+        );
+        let edited = match action {
+            Some(action) => {
+                let id = audit
+                    .update(|log| {
+                        log.record(
+                            cfg.channel_chat_id,
+                            user_id,
+                            describe_user(&invite.user),
+                            action,
+                        )
+                    })
+                    .await;
+                log::debug!("{:?} of {} recorded as #{}", action, user_id, id);
+
+                edit.reply_markup(undo_keyboard(id, user_id)).await
+            }
+            None => edit.await,
+        };
+
+        if let Err(err) = edited {
+            log::error!(
+                "Failed to add the outcome to notification ({}): {}",
+                invite.message_id,
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs [`revoke_expired_personal_links`] periodically, if personal invites are enabled.
+pub fn spawn_personal_link_expiry(bot: Bot, cfg: Arc<Config>, invites: Arc<Storage<Invites>>) {
+    if !cfg.personal_invites {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PERSONAL_LINK_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            revoke_expired_personal_links(&bot, &cfg, &invites).await;
+        }
+    });
+}
+
+/// Revokes personal invite links which weren't used in time.
+pub async fn revoke_expired_personal_links(bot: &Bot, cfg: &Config, invites: &Storage<Invites>) {
+    let urls = invites
+        .read(|invites| invites.expired_personal_links(Utc::now()))
+        .await;
+
+    for url in urls {
+        //
+        // the link is forgotten only once it's revoked, otherwise it's tried again next time
+        //
+        if let Err(err) = bot.revoke_chat_invite_link(cfg.channel_chat_id, &url).await {
+            log::error!("Failed to revoke expired personal link {}: {}", url, err);
+            continue;
+        }
+
+        if let Some(link) = invites
+            .update(|invites| invites.take_personal_link(&url))
+            .await
+        {
+            log::debug!(
+                "Personal link of {} has expired and been revoked",
+                link.user_id
+            );
+        }
+    }
+}
+
+/// Sends a summary of users added by admins to work chat every week, if it's enabled.
+pub fn spawn_invites_report(bot: Bot, cfg: Arc<Config>, invites: Arc<Storage<Invites>>) {
+    if !cfg.invites_report {
+        return;
+    }
+
+    tokio::spawn(async move {
+        //
+        // the first report is sent in a week after reports are turned on, not right after start
+        //
+        if invites
+            .read(|invites| invites.last_report().is_none())
+            .await
+        {
+            invites
+                .update(|invites| invites.set_last_report(Utc::now()))
+                .await;
+        }
+
+        let period = chrono::Duration::days(INVITES_REPORT_DAYS as i64);
+        let mut interval = tokio::time::interval(INVITES_REPORT_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let now = Utc::now();
+            let Some(since) = invites
+                .read(|invites| invites.last_report())
+                .await
+                .filter(|&last| now - last >= period)
+            else {
+                continue;
+            };
+            let summary = invites.read(|invites| invites.summary(since)).await;
+            //
+            // a report delayed by downtime covers the whole time since the last one
+            //
+            let days = (now - since).num_days() as u64;
+
+            match bot
+                .send_message(cfg.work_chat_id, describe_summary(&summary, days))
+                .await
+            {
+                Ok(_) => {
+                    log::debug!("A message was sent to work chat ({})", cfg.work_chat_id);
+
+                    invites.update(|invites| invites.set_last_report(now)).await;
+                }
+                //
+                // the report is tried again on the next tick
+                //
+                Err(err) => log::error!("Failed to send the report about invites: {}", err),
+            }
+        }
+    });
+}
+
+/// Drops outdated statuses from the membership cache once in a ttl.
+pub fn spawn_membership_pruning(cfg: Arc<Config>, membership: Arc<MembershipCache>) {
+    if cfg.membership_cache_ttl.is_zero() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cfg.membership_cache_ttl);
+
+        loop {
+            interval.tick().await;
+
+            membership.prune().await;
+        }
+    });
+}
+
+/// Keeps administrators of channel's chat cached, so they are rarely requested while handling updates.
+pub fn spawn_admin_refresh(bot: Bot, cfg: Arc<Config>, admins: Arc<AdminCache>) {
+    //
+    // nothing is cached then, administrators are requested every time
+    //
+    if cfg.admin_cache_ttl.is_zero() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cfg.admin_cache_ttl);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = admins.refresh(&bot, cfg.channel_chat_id).await {
+                log::error!("Failed to refresh administrators of chat: {}", err);
+            }
+        }
+    });
+}
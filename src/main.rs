@@ -1,12 +1,43 @@
+mod admins;
+mod audit;
+mod captcha;
 mod config;
 mod filters;
 mod handlers;
+mod import;
+mod invites;
+mod jobs;
+mod members;
+mod membership;
 mod misc;
+mod policy;
+mod posts;
+mod quarantine;
+mod raid;
+mod rights;
+mod rules;
+mod sender_chats;
+mod storage;
+mod transition;
+mod verified;
 
+use admins::AdminCache;
+use audit::AuditLog;
 use config::Config;
-use std::{collections::HashSet, sync::Arc};
+use invites::Invites;
+use jobs::Reconciliation;
+use members::MemberIndex;
+use membership::MembershipCache;
+use posts::ChannelPosts;
+use quarantine::Quarantine;
+use raid::{JoinRate, Lockdown};
+use rules::Admission;
+use sender_chats::SenderChats;
+use std::{collections::HashSet, path::Path, sync::Arc};
+use storage::Storage;
 use teloxide::prelude::*;
 use tokio::sync::Mutex;
+use verified::VerifiedUsers;
 
 #[tokio::main]
 async fn main() {
@@ -16,17 +47,90 @@ async fn main() {
 
     pretty_env_logger::init();
 
+    let config = Arc::new(Config::new());
+    let members = Arc::new(Storage::<MemberIndex>::open(&config.data_dir, "members"));
+
+    //
+    // other arguments are left to whatever runs Bot, they don't stop it from starting
+    //
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("import") {
+        let Some(path) = args.next() else {
+            eprintln!("Usage: che-guarde-bot [import <path to result.json>]");
+            std::process::exit(1);
+        };
+
+        match import::import(Path::new(&path), config.channel_chat_id, &members).await {
+            Ok(count) => println!("{} users imported", count),
+            Err(err) => {
+                eprintln!("Failed to import {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let membership = Arc::new(MembershipCache::new(
+        config.membership_cache_ttl,
+        config.compare_membership_cache,
+    ));
+    let admission = Arc::new(
+        Admission::parse(&config.admission_rules, &config, membership.clone())
+            .unwrap_or_else(|err| panic!("Failed to parse ADMISSION_RULES: {}", err)),
+    );
+    let bot = Bot::from_env();
+    let ids = Arc::new(Mutex::new(HashSet::<UserId>::new()));
+    let audit = Arc::new(Storage::<AuditLog>::open(&config.data_dir, "audit"));
+    if audit.read(AuditLog::has_legacy_pardons).await {
+        audit
+            .update(|log| log.migrate_pardons(misc::date_after(config.pardon_ttl)))
+            .await;
+    }
+    let verified = Arc::new(VerifiedUsers::new(config.verified_ttl));
+    let sender_chats = Arc::new(Storage::<SenderChats>::open(
+        &config.data_dir,
+        "sender_chats",
+    ));
+    let quarantine = Arc::new(Storage::<Quarantine>::open(&config.data_dir, "quarantine"));
+    let join_rate = Arc::new(JoinRate::new(config.raid_window));
+    let lockdown = Arc::new(Storage::<Lockdown>::open(&config.data_dir, "lockdown"));
+    let invites = Arc::new(Storage::<Invites>::open(&config.data_dir, "invites"));
+    let admins = Arc::new(AdminCache::new(config.admin_cache_ttl));
+    let posts = Arc::new(Storage::<ChannelPosts>::open(&config.data_dir, "posts"));
+    let reconciliation = Arc::new(Reconciliation::default());
+
     let mut handlers = dptree::entry()
         .branch(
             Update::filter_chat_member()
+                .chain(handlers::update::remember_member())
+                .chain(handlers::update::forget_admins_on_promotion())
+                .chain(handlers::update::report_admin_changes())
+                .chain(handlers::update::revoke_used_personal_link())
+                .chain(handlers::update::forget_join_link())
+                .branch(handlers::update::bot_was_added_to_channel_chat())
                 .branch(handlers::update::user_was_invited_to_chat_by_admin())
                 .branch(handlers::update::user_joined_channel_chat())
                 .branch(handlers::update::user_left_or_was_kicked_from_channel()),
         )
+        .branch(Update::filter_my_chat_member().branch(handlers::update::bot_status_changed()))
         .branch(
             Update::filter_message()
+                .chain(handlers::message::remember_sender())
+                .chain(handlers::message::remember_channel_post())
                 .branch(handlers::message::user_was_kicked_from_channel_chat())
-                .branch(handlers::message::sent_checkhealth_command()),
+                .branch(handlers::message::sent_work_chat_command())
+                .branch(handlers::message::sent_checkhealth_command())
+                .branch(handlers::message::sent_invite_command())
+                .branch(handlers::message::user_sent_message_on_behalf_of_chat())
+                .branch(handlers::message::unverified_user_sent_message()),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .branch(handlers::callback::admin_pressed_undo_button())
+                .branch(handlers::callback::admin_pressed_allow_sender_chat_button())
+                .branch(handlers::callback::user_pressed_captcha_button())
+                .branch(handlers::callback::admin_pressed_keep_invited_button()),
         );
 
     if cfg!(debug_assertions) {
@@ -35,13 +139,79 @@ async fn main() {
             .branch(Update::filter_message().branch(handlers::message::maintainer_sent_command()));
     }
 
+    jobs::spawn_reconciliation(
+        bot.clone(),
+        config.clone(),
+        members.clone(),
+        audit.clone(),
+        ids.clone(),
+        reconciliation.clone(),
+    );
+    jobs::spawn_ban_expiry(bot.clone(), config.clone(), audit.clone());
+    jobs::spawn_invite_expiry(bot.clone(), config.clone(), audit.clone(), invites.clone());
+    jobs::spawn_admin_refresh(bot.clone(), config.clone(), admins.clone());
+    jobs::spawn_membership_pruning(config.clone(), membership.clone());
+    jobs::spawn_invites_report(bot.clone(), config.clone(), invites.clone());
+    jobs::spawn_personal_link_expiry(bot.clone(), config.clone(), invites.clone());
+    jobs::spawn_captcha_expiry(
+        bot.clone(),
+        config.clone(),
+        audit.clone(),
+        quarantine.clone(),
+    );
+    jobs::spawn_quarantine_recovery(
+        bot.clone(),
+        config.clone(),
+        audit.clone(),
+        verified.clone(),
+        admission.clone(),
+        quarantine.clone(),
+        lockdown.clone(),
+    );
+    jobs::spawn_lockdown_expiry(
+        bot.clone(),
+        config.clone(),
+        audit.clone(),
+        verified.clone(),
+        admission.clone(),
+        quarantine.clone(),
+        lockdown.clone(),
+    );
+
     log::info!("Starting bot...");
-    Dispatcher::builder(Bot::from_env(), handlers)
+    Dispatcher::builder(bot, handlers)
+        .distribution_function(misc::affected_user)
         .dependencies(dptree::deps![
             // config
-            Arc::new(Config::new()),
+            config,
             // kicked or banned user ids
-            Arc::new(Mutex::new(HashSet::<UserId>::new()))
+            ids,
+            // automated actions which can be undone
+            audit,
+            // users seen in chats
+            members,
+            // users allowed to write into chat
+            verified,
+            // chats users write on behalf of
+            sender_chats,
+            // rules of admission into chat
+            admission,
+            // newcomers who can only read chat until they are checked
+            quarantine,
+            // recent joins to chat
+            join_rate,
+            // state of chat during a raid
+            lockdown,
+            // users added by admins who wait for confirmation
+            invites,
+            // administrators of chats
+            admins,
+            // statuses of users in channel and chat
+            membership,
+            // posts of channel forwarded into chat
+            posts,
+            // whether reconciliation is running
+            reconciliation
         ])
         .enable_ctrlc_handler()
         .build()
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, ChatMemberStatus, User, UserId};
+
+use crate::misc::describe_name;
+
+/// Messages of a user don't move the last seen date if it's more recent than that.
+const LAST_SEEN_PRECISION_SECS: i64 = 60 * 60;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Member {
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Last known status in chat, `None` if user was seen but the status is unknown.
+    pub status: Option<ChatMemberStatus>,
+}
+
+impl Member {
+    pub fn describe(&self) -> String {
+        describe_name(&self.first_name, self.username.as_ref())
+    }
+}
+
+pub fn describe_status(status: Option<ChatMemberStatus>) -> String {
+    status.map_or_else(
+        || "unknown".to_owned(),
+        |status| format!("{:?}", status).to_lowercase(),
+    )
+}
+
+/// Users Bot has ever seen in chats.
+/// Telegram doesn't allow bots to list members of large chats, so this is the only way to know them.
+#[derive(Default, Serialize, Deserialize)]
+pub struct MemberIndex {
+    chats: HashMap<ChatId, BTreeMap<UserId, Member>>,
+}
+
+impl MemberIndex {
+    /// Updates user's data. Status is kept as is if `None` is passed.
+    pub fn observe(&mut self, chat_id: ChatId, user: &User, status: Option<ChatMemberStatus>) {
+        let now = Utc::now();
+
+        self.chats
+            .entry(chat_id)
+            .or_default()
+            .entry(user.id)
+            .and_modify(|member| {
+                member.first_name.clone_from(&user.first_name);
+                member.last_name.clone_from(&user.last_name);
+                member.username.clone_from(&user.username);
+                member.last_seen = now;
+                member.status = status.or(member.status);
+            })
+            .or_insert_with(|| Member {
+                first_name: user.first_name.clone(),
+                last_name: user.last_name.clone(),
+                username: user.username.clone(),
+                first_seen: now,
+                last_seen: now,
+                status,
+            });
+    }
+
+    /// Returns `true` if nothing but the last seen date would be changed by a message of user
+    /// and that date is recent enough, so the index doesn't have to be saved on every message.
+    pub fn is_up_to_date(&self, chat_id: ChatId, user: &User, now: DateTime<Utc>) -> bool {
+        self.get(chat_id, user.id).is_some_and(|member| {
+            member.first_name == user.first_name
+                && member.last_name == user.last_name
+                && member.username == user.username
+                && (now - member.last_seen).num_seconds() < LAST_SEEN_PRECISION_SECS
+        })
+    }
+
+    /// Adds a user seen at the given date, e.g. in an exported chat history.
+    /// Status is updated only if the date is not older than the last time user was seen.
+    pub fn import(
+        &mut self,
+        chat_id: ChatId,
+        user_id: UserId,
+        name: &str,
+        date: DateTime<Utc>,
+        status: Option<ChatMemberStatus>,
+    ) {
+        self.chats
+            .entry(chat_id)
+            .or_default()
+            .entry(user_id)
+            .and_modify(|member| {
+                member.first_seen = member.first_seen.min(date);
+                if date >= member.last_seen {
+                    member.last_seen = date;
+                    member.status = status.or(member.status);
+                }
+            })
+            .or_insert_with(|| Member {
+                //
+                // export has only full names
+                //
+                first_name: name.to_owned(),
+                last_name: None,
+                username: None,
+                first_seen: date,
+                last_seen: date,
+                status,
+            });
+    }
+
+    pub fn user_ids(&self, chat_id: ChatId) -> Vec<UserId> {
+        self.chats
+            .get(&chat_id)
+            .map(|members| members.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, chat_id: ChatId, user_id: UserId) -> Option<&Member> {
+        self.chats.get(&chat_id)?.get(&user_id)
+    }
+
+    /// Looks for a user by username, "@" at the beginning is optional.
+    pub fn find_by_username(&self, chat_id: ChatId, username: &str) -> Option<(UserId, &Member)> {
+        let username = username.trim_start_matches('@');
+
+        self.chats
+            .get(&chat_id)?
+            .iter()
+            .find(|(_, member)| {
+                member
+                    .username
+                    .as_ref()
+                    .is_some_and(|known| known.eq_ignore_ascii_case(username))
+            })
+            .map(|(&user_id, member)| (user_id, member))
+    }
+
+    /// Number of users per last known status.
+    pub fn count_by_status(&self, chat_id: ChatId) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+
+        for member in self
+            .chats
+            .get(&chat_id)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+        {
+            *counts.entry(describe_status(member.status)).or_default() += 1;
+        }
+
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use teloxide::types::{ChatId, ChatMemberStatus, User, UserId};
+
+    use super::MemberIndex;
+
+    const CHAT_ID: ChatId = ChatId(-1002);
+    const USER_ID: UserId = UserId(42);
+
+    fn user(username: &str) -> User {
+        User {
+            id: USER_ID,
+            is_bot: false,
+            first_name: "Ivan".to_owned(),
+            last_name: None,
+            username: Some(username.to_owned()),
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    #[test]
+    fn unknown_user_is_not_up_to_date() {
+        let index = MemberIndex::default();
+
+        assert!(!index.is_up_to_date(CHAT_ID, &user("ivan"), Utc::now()));
+    }
+
+    #[test]
+    fn known_user_is_up_to_date_until_something_changes() {
+        let now = Utc::now();
+        let mut index = MemberIndex::default();
+
+        index.observe(CHAT_ID, &user("ivan"), None);
+
+        assert!(index.is_up_to_date(CHAT_ID, &user("ivan"), now));
+        assert!(!index.is_up_to_date(CHAT_ID, &user("ivan_petrov"), now));
+        assert!(!index.is_up_to_date(ChatId(-1), &user("ivan"), now));
+        //
+        // the last seen date is moved once it's old enough
+        //
+        assert!(!index.is_up_to_date(CHAT_ID, &user("ivan"), now + Duration::hours(2)));
+    }
+
+    #[test]
+    fn imports_new_user() {
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut index = MemberIndex::default();
+
+        index.import(
+            CHAT_ID,
+            USER_ID,
+            "Ivan Petrov",
+            date,
+            Some(ChatMemberStatus::Member),
+        );
+
+        let member = index.get(CHAT_ID, USER_ID).unwrap();
+        assert_eq!(member.first_name, "Ivan Petrov");
+        assert_eq!(member.username, None);
+        assert_eq!(member.first_seen, date);
+        assert_eq!(member.last_seen, date);
+        assert_eq!(member.status, Some(ChatMemberStatus::Member));
+        assert_eq!(index.user_ids(CHAT_ID), vec![USER_ID]);
+        assert!(index.user_ids(ChatId(-1)).is_empty());
+    }
+
+    #[test]
+    fn import_keeps_newer_status() {
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut index = MemberIndex::default();
+
+        index.import(
+            CHAT_ID,
+            USER_ID,
+            "Ivan",
+            date,
+            Some(ChatMemberStatus::Member),
+        );
+        index.import(
+            CHAT_ID,
+            USER_ID,
+            "Ivan",
+            date - Duration::days(1),
+            Some(ChatMemberStatus::Left),
+        );
+
+        let member = index.get(CHAT_ID, USER_ID).unwrap();
+        assert_eq!(member.first_seen, date - Duration::days(1));
+        assert_eq!(member.last_seen, date);
+        assert_eq!(member.status, Some(ChatMemberStatus::Member));
+    }
+
+    #[test]
+    fn import_updates_status_by_later_event() {
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut index = MemberIndex::default();
+
+        index.import(
+            CHAT_ID,
+            USER_ID,
+            "Ivan",
+            date,
+            Some(ChatMemberStatus::Member),
+        );
+        index.import(
+            CHAT_ID,
+            USER_ID,
+            "Ivan",
+            date + Duration::days(1),
+            Some(ChatMemberStatus::Left),
+        );
+        //
+        // a message doesn't tell the status, so it's kept
+        //
+        index.import(CHAT_ID, USER_ID, "Ivan", date + Duration::days(2), None);
+
+        let member = index.get(CHAT_ID, USER_ID).unwrap();
+        assert_eq!(member.first_seen, date);
+        assert_eq!(member.last_seen, date + Duration::days(2));
+        assert_eq!(member.status, Some(ChatMemberStatus::Left));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, ChatMember, UserId},
+    Bot, RequestError,
+};
+use tokio::sync::Mutex;
+
+/// Statuses of users in chats taken from `chat_member` updates, so they aren't requested from Telegram on every event.
+/// A status older than the ttl or a missing one is requested from Telegram.
+pub struct MembershipCache {
+    ttl: Duration,
+    /// Cached statuses are compared to the ones requested from Telegram to find out whether the cache is reliable.
+    compare: bool,
+    members: Mutex<HashMap<(ChatId, UserId), (ChatMember, Instant)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Cached statuses compared to Telegram, they aren't hits as Telegram is requested anyway.
+    comparisons: AtomicU64,
+    drifts: AtomicU64,
+}
+
+impl MembershipCache {
+    pub fn new(ttl: Duration, compare: bool) -> Self {
+        Self {
+            ttl,
+            compare,
+            members: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            comparisons: AtomicU64::new(0),
+            drifts: AtomicU64::new(0),
+        }
+    }
+
+    /// Remembers the status of a user seen in an update.
+    pub async fn observe(&self, chat_id: ChatId, member: ChatMember) {
+        //
+        // nothing would ever be taken from the cache
+        //
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        self.members
+            .lock()
+            .await
+            .insert((chat_id, member.user.id), (member, Instant::now()));
+    }
+
+    pub async fn get_chat_member(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<ChatMember, RequestError> {
+        let cached = {
+            let mut members = self.members.lock().await;
+
+            match members.get(&(chat_id, user_id)) {
+                Some((member, observed_at)) if observed_at.elapsed() < self.ttl => {
+                    Some(member.clone())
+                }
+                Some(_) => {
+                    members.remove(&(chat_id, user_id));
+
+                    None
+                }
+                None => None,
+            }
+        };
+
+        match cached {
+            Some(cached) if !self.compare => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                Ok(cached)
+            }
+            Some(cached) => {
+                self.comparisons.fetch_add(1, Ordering::Relaxed);
+
+                let member = self.request(bot, chat_id, user_id).await?;
+                if member.status() != cached.status() {
+                    self.drifts.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "Cached status of {} in chat ({}) is {:?}, but Telegram says {:?}",
+                        user_id,
+                        chat_id,
+                        cached.status(),
+                        member.status()
+                    );
+                }
+
+                Ok(member)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+
+                self.request(bot, chat_id, user_id).await
+            }
+        }
+    }
+
+    /// Forgets statuses older than the ttl, so users seen once don't stay in memory forever.
+    pub async fn prune(&self) {
+        let mut members = self.members.lock().await;
+        let before = members.len();
+
+        members.retain(|_, (_, observed_at)| observed_at.elapsed() < self.ttl);
+        log::debug!(
+            "{} outdated statuses are dropped from cache, {} left",
+            before - members.len(),
+            members.len()
+        );
+    }
+
+    async fn request(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<ChatMember, RequestError> {
+        let member = bot.get_chat_member(chat_id, user_id).await?;
+        self.observe(chat_id, member.clone()).await;
+
+        Ok(member)
+    }
+
+    /// Numbers of cache hits, misses, comparisons and found drifts in the way they are shown in work chat.
+    pub fn describe_stats(&self) -> String {
+        let mut stats = format!(
+            "участники: попаданий {}, промахов {}",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed)
+        );
+        if self.compare {
+            stats.push_str(&format!(
+                ", сверок с Telegram {}, расхождений {}",
+                self.comparisons.load(Ordering::Relaxed),
+                self.drifts.load(Ordering::Relaxed)
+            ));
+        }
+
+        stats
+    }
+}
@@ -1,12 +1,24 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, future::Future, pin::Pin, time::Duration};
+
+use chrono::{DateTime, Utc};
 
 use teloxide::{
     dispatching::DpHandlerDescription,
     dptree::{di::DependencyMap, Handler},
+    types::{Chat, MessageKind, Update, UpdateKind, User, UserId},
+};
+
+use crate::{
+    audit::UNDO_CALLBACK_PREFIX, captcha::CAPTCHA_CALLBACK_PREFIX,
+    invites::KEEP_INVITED_CALLBACK_PREFIX,
 };
 
 pub type ReturnType<Output> = Handler<'static, DependencyMap, Output, DpHandlerDescription>;
 
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub const DEFAULT_USER_USERNAME: &str = "(пользователь скрыл свой ID)";
+
 pub fn create_username_or_default<'opt>(
     default: &'opt str,
     username: Option<&'opt String>,
@@ -17,12 +29,115 @@ pub fn create_username_or_default<'opt>(
     )
 }
 
+/// User's name in the way it's shown in notifications: "first name, @username".
+pub fn describe_name(first_name: &String, username: Option<&String>) -> String {
+    format!(
+        "{}{}",
+        first_name.end_with_comma_if_not_empty(),
+        create_username_or_default(DEFAULT_USER_USERNAME, username)
+    )
+}
+
+pub fn describe_user(user: &User) -> String {
+    describe_name(&user.first_name, user.username.as_ref())
+}
+
+/// Chat's name in the way it's shown in notifications: "title (@username, id)".
+pub fn describe_chat(chat: &Chat) -> String {
+    match chat.username() {
+        Some(username) => format!(
+            "{} (@{}, {})",
+            chat.title().unwrap_or_default(),
+            username,
+            chat.id
+        ),
+        None => format!("{} ({})", chat.title().unwrap_or_default(), chat.id),
+    }
+}
+
+/// A user the update is about. Updates about one user are handled one by one in the order they came,
+/// while updates about different users are handled concurrently.
+pub fn affected_user(update: &Update) -> Option<UserId> {
+    match &update.kind {
+        UpdateKind::ChatMember(req) | UpdateKind::MyChatMember(req) => {
+            Some(req.new_chat_member.user.id)
+        }
+        UpdateKind::Message(msg) => match &msg.kind {
+            //
+            // service messages are about other users than their senders
+            //
+            MessageKind::LeftChatMember(left) => Some(left.left_chat_member.id),
+            //
+            // a message about several added users can't be keyed by all of them, but it's only used
+            // to remember the sender, and every added user gets their own chat member update
+            //
+            MessageKind::NewChatMembers(new) => new.new_chat_members.first().map(|user| user.id),
+            _ => msg.from().map(|user| user.id),
+        },
+        //
+        // buttons act on the user they are about, not on the one who pressed them
+        //
+        UpdateKind::CallbackQuery(query) => Some(
+            query
+                .data
+                .as_deref()
+                .and_then(button_target)
+                .unwrap_or(query.from.id),
+        ),
+        _ => update.user().map(|user| user.id),
+    }
+}
+
+/// A user the button is about. Buttons allowing sender chats are about chats, they only change
+/// the list of allowed chats, so they are keyed by the admin who pressed them.
+fn button_target(data: &str) -> Option<UserId> {
+    let user_id = if let Some(args) = data.strip_prefix(UNDO_CALLBACK_PREFIX) {
+        //
+        // buttons sent before the user was added to them have no user
+        //
+        args.split_once(':')?.1
+    } else if let Some(args) = data.strip_prefix(CAPTCHA_CALLBACK_PREFIX) {
+        args.split_once(':')?.0
+    } else {
+        data.strip_prefix(KEEP_INVITED_CALLBACK_PREFIX)?
+    };
+
+    user_id.parse().ok().map(UserId)
+}
+
+pub fn date_after(duration: Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(duration.as_secs() as i64)
+}
+
+/// Duration in the way it's shown in notifications: "1 д 2 ч 3 мин", or "30 сек" if it's shorter than a minute.
+pub fn describe_duration(duration: chrono::Duration) -> String {
+    if duration.num_minutes() < 1 {
+        return format!("{} сек", duration.num_seconds().max(0));
+    }
+
+    let minutes = duration.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{} д", days));
+    }
+    if hours > 0 {
+        parts.push(format!("{} ч", hours));
+    }
+    if minutes > 0 || parts.is_empty() {
+        parts.push(format!("{} мин", minutes));
+    }
+
+    parts.join(" ")
+}
+
 pub trait FormatArgument {
-    fn end_with_comma_if_not_empty(&self) -> Cow<str>;
+    fn end_with_comma_if_not_empty(&self) -> Cow<'_, str>;
 }
 
 impl FormatArgument for String {
-    fn end_with_comma_if_not_empty(&self) -> Cow<str> {
+    fn end_with_comma_if_not_empty(&self) -> Cow<'_, str> {
         if !self.is_empty() {
             Cow::Owned(format!("{}, ", self))
         } else {
@@ -30,3 +145,29 @@ impl FormatArgument for String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::describe_duration;
+
+    #[test]
+    fn describes_durations() {
+        assert_eq!(describe_duration(Duration::minutes(1)), "1 мин");
+        assert_eq!(describe_duration(Duration::seconds(90)), "1 мин");
+        assert_eq!(describe_duration(Duration::hours(2)), "2 ч");
+        assert_eq!(describe_duration(Duration::days(7)), "7 д");
+        assert_eq!(
+            describe_duration(Duration::days(1) + Duration::hours(2) + Duration::minutes(3)),
+            "1 д 2 ч 3 мин"
+        );
+    }
+
+    #[test]
+    fn describes_durations_shorter_than_minute() {
+        assert_eq!(describe_duration(Duration::seconds(30)), "30 сек");
+        assert_eq!(describe_duration(Duration::zero()), "0 сек");
+        assert_eq!(describe_duration(Duration::seconds(-5)), "0 сек");
+    }
+}
@@ -0,0 +1,332 @@
+use std::{str::FromStr, time::Duration};
+
+use teloxide::{
+    payloads::{BanChatMemberSetters, RestrictChatMemberSetters},
+    requests::Requester,
+    types::{ChatId, ChatMemberKind, ChatPermissions, UserId},
+    Bot, RequestError,
+};
+
+use crate::{audit::Action, misc::date_after};
+
+/// What's done to a user who doesn't follow the rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Measure {
+    /// Work chat is notified, user isn't touched.
+    Notify,
+    /// User is able to read chat, but not to write into it.
+    ReadOnly,
+    /// User isn't able to write into chat for a while.
+    Mute(Duration),
+    /// User is removed from chat, but is able to join it again.
+    Kick,
+    /// User is removed from chat and banned in it for a while, or forever if there is no duration.
+    Ban(Option<Duration>),
+    /// User's status in channel is applied to chat, see [`mirror_channel_status`].
+    Mirror,
+}
+
+/// Telegram treats restrictions longer than this as permanent ones.
+const MAX_RESTRICTION_HOURS: u64 = 366 * 24;
+
+/// Duration of a ban or a mute set in hours. Telegram treats restrictions shorter than 30 seconds
+/// or longer than 366 days as permanent ones, so such durations are rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestrictionHours(pub Duration);
+
+impl FromStr for RestrictionHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hours = s
+            .trim()
+            .parse::<u64>()
+            .map_err(|err| format!("wrong number of hours {}: {}", s, err))?;
+
+        if !(1..=MAX_RESTRICTION_HOURS).contains(&hours) {
+            return Err(format!(
+                "number of hours should be from 1 to {}: {}",
+                MAX_RESTRICTION_HOURS, hours
+            ));
+        }
+
+        Ok(Self(Duration::from_secs(hours * 60 * 60)))
+    }
+}
+
+impl FromStr for Measure {
+    type Err = String;
+
+    /// Durations are set in hours, e.g. "mute(12)" or "ban(72)", see [`RestrictionHours`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hours = |arg: &str| {
+            arg.strip_suffix(')')
+                .ok_or_else(|| format!("wrong duration in {}", s))?
+                .parse::<RestrictionHours>()
+                .map(|hours| hours.0)
+                .map_err(|err| format!("wrong duration in {}: {}", s, err))
+        };
+
+        match s.trim() {
+            "notify" => Ok(Self::Notify),
+            "read_only" => Ok(Self::ReadOnly),
+            "kick" => Ok(Self::Kick),
+            "ban" => Ok(Self::Ban(None)),
+            "mirror" => Ok(Self::Mirror),
+            s => match s.split_once('(') {
+                Some(("mute", arg)) => Ok(Self::Mute(hours(arg)?)),
+                Some(("ban", arg)) => Ok(Self::Ban(Some(hours(arg)?))),
+                _ => Err(format!("unknown action: {}", s)),
+            },
+        }
+    }
+}
+
+impl Measure {
+    /// Bans with no explicit duration get the given one.
+    pub fn with_ban_duration(self, duration: Option<Duration>) -> Self {
+        match self {
+            Self::Ban(None) => Self::Ban(duration),
+            measure => measure,
+        }
+    }
+}
+
+/// Applies the measure to user in chat.
+/// Status in channel is used by [`Measure::Mirror`] only.
+/// Returns `None` if user wasn't touched.
+pub async fn apply_measure(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    measure: Measure,
+    channel_status: Option<&ChatMemberKind>,
+) -> Result<Option<Action>, RequestError> {
+    match measure {
+        Measure::Notify => Ok(None),
+        Measure::ReadOnly => {
+            bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
+                .await?;
+            log::debug!(
+                "{} has been restricted to read only in chat ({})",
+                user_id,
+                chat_id
+            );
+
+            Ok(Some(Action::Restrict { until: None }))
+        }
+        Measure::Mute(duration) => {
+            let until = date_after(duration);
+
+            bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
+                .until_date(until)
+                .await?;
+            log::debug!(
+                "{} has been muted until {} in chat ({})",
+                user_id,
+                until,
+                chat_id
+            );
+
+            Ok(Some(Action::Restrict { until: Some(until) }))
+        }
+        Measure::Kick => {
+            //
+            // it only kicks user from chat
+            //
+            bot.unban_chat_member(chat_id, user_id).await?;
+            log::debug!(
+                "{} has been only kicked (no ban) from chat ({})",
+                user_id,
+                chat_id
+            );
+
+            Ok(Some(Action::Kick))
+        }
+        Measure::Ban(None) => kick_and_ban(bot, chat_id, user_id).await.map(Some),
+        Measure::Ban(Some(duration)) => {
+            let until = date_after(duration);
+
+            bot.ban_chat_member(chat_id, user_id)
+                .until_date(until)
+                .await?;
+            log::debug!(
+                "{} has been banned until {} in chat ({})",
+                user_id,
+                until,
+                chat_id
+            );
+
+            Ok(Some(Action::TempBan { until }))
+        }
+        Measure::Mirror => {
+            match channel_status {
+                Some(channel_status) => {
+                    mirror_channel_status(bot, chat_id, user_id, channel_status).await
+                }
+                None => {
+                    log::warn!("Status in channel is unknown, so it can't be mirrored. User won't be touched.");
+
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// Gives user back default permissions of chat.
+pub async fn lift_restrictions(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<(), RequestError> {
+    let permissions = bot
+        .get_chat(chat_id)
+        .await?
+        .permissions()
+        .unwrap_or_else(ChatPermissions::all);
+
+    bot.restrict_chat_member(chat_id, user_id, permissions)
+        .await?;
+    log::debug!(
+        "restrictions of {} have been lifted in chat ({})",
+        user_id,
+        chat_id
+    );
+
+    Ok(())
+}
+
+/// Applies user's status in channel to chat: one who left channel is kicked from chat,
+/// and one who was banned in channel is banned in chat too.
+/// Returns `None` if status is not expected to be mirrored and user wasn't touched.
+pub async fn mirror_channel_status(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    channel_status: &ChatMemberKind,
+) -> Result<Option<Action>, RequestError> {
+    match channel_status {
+        ChatMemberKind::Left => {
+            //
+            // it only kicks user from chat
+            //
+            bot.unban_chat_member(chat_id, user_id).await?;
+            log::debug!(
+                "{} has been only kicked (no ban) from chat ({})",
+                user_id,
+                chat_id
+            );
+
+            Ok(Some(Action::Kick))
+        }
+        ChatMemberKind::Banned(_) => {
+            //
+            // it additionally bans user
+            //
+            bot.kick_chat_member(chat_id, user_id).await?;
+            log::debug!(
+                "{} has been kicked (w/ ban) from chat ({})",
+                user_id,
+                chat_id
+            );
+
+            Ok(Some(Action::Ban))
+        }
+        unexpected_kind => {
+            log::warn!(
+                "{:?} is not expected to be mirrored. User won't be touched.",
+                unexpected_kind
+            );
+
+            Ok(None)
+        }
+    }
+}
+
+/// Removes user from chat and bans them there.
+/// Returns [`Action::Kick`] if user was removed, but the ban failed.
+pub async fn kick_and_ban(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<Action, RequestError> {
+    bot.kick_chat_member(chat_id, user_id).await?;
+    log::debug!("{} has been kicked from chat ({})", user_id, chat_id);
+
+    if let Err(err) = bot.ban_chat_member(chat_id, user_id).await {
+        log::error!(
+            "Partial error. Failed to ban user in chat.\nUser: {}.\nChat: {}.\nError: {}",
+            user_id,
+            chat_id,
+            err
+        );
+
+        Ok(Action::Kick)
+    } else {
+        log::debug!("{} has been banned in chat ({})", user_id, chat_id);
+
+        Ok(Action::Ban)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Measure::*, *};
+
+    fn hours(hours: u64) -> Duration {
+        Duration::from_secs(hours * 60 * 60)
+    }
+
+    #[test]
+    fn parses_measures() {
+        assert_eq!("notify".parse(), Ok(Notify));
+        assert_eq!("read_only".parse(), Ok(ReadOnly));
+        assert_eq!(" kick ".parse(), Ok(Kick));
+        assert_eq!("ban".parse(), Ok(Ban(None)));
+        assert_eq!("mirror".parse(), Ok(Mirror));
+        assert_eq!("mute(12)".parse(), Ok(Mute(hours(12))));
+        assert_eq!("ban(72)".parse(), Ok(Ban(Some(hours(72)))));
+    }
+
+    #[test]
+    fn rejects_wrong_measures() {
+        assert!("delete".parse::<Measure>().is_err());
+        assert!("mute".parse::<Measure>().is_err());
+        assert!("mute(12".parse::<Measure>().is_err());
+        assert!("mute(twelve)".parse::<Measure>().is_err());
+        assert!("kick(12)".parse::<Measure>().is_err());
+    }
+
+    #[test]
+    fn restriction_hours_bounds() {
+        assert_eq!("1".parse(), Ok(RestrictionHours(hours(1))));
+        assert_eq!(
+            MAX_RESTRICTION_HOURS.to_string().parse(),
+            Ok(RestrictionHours(hours(MAX_RESTRICTION_HOURS)))
+        );
+
+        assert!("0".parse::<RestrictionHours>().is_err());
+        assert!((MAX_RESTRICTION_HOURS + 1)
+            .to_string()
+            .parse::<RestrictionHours>()
+            .is_err());
+        assert!("-1".parse::<RestrictionHours>().is_err());
+        assert!("ban(0)".parse::<Measure>().is_err());
+    }
+
+    #[test]
+    fn ban_duration() {
+        assert_eq!(
+            Ban(None).with_ban_duration(Some(hours(24))),
+            Ban(Some(hours(24)))
+        );
+        assert_eq!(
+            Ban(Some(hours(1))).with_ban_duration(Some(hours(24))),
+            Ban(Some(hours(1)))
+        );
+        assert_eq!(Kick.with_ban_duration(Some(hours(24))), Kick);
+    }
+}
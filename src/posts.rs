@@ -0,0 +1,44 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, Message};
+
+/// How many of the latest posts are remembered. Comments to older posts are treated as regular messages.
+const MAX_POSTS: usize = 10_000;
+
+/// Posts of channel which were forwarded into its chat automatically. Threads of comments start from them.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ChannelPosts {
+    /// Ids of forwarded posts in channel's chat, they are ids of threads of comments as well.
+    ids: BTreeSet<i32>,
+}
+
+impl ChannelPosts {
+    pub fn add(&mut self, id: i32) {
+        self.ids.insert(id);
+
+        while self.ids.len() > MAX_POSTS {
+            self.ids.pop_first();
+        }
+    }
+
+    pub fn contains(&self, id: i32) -> bool {
+        self.ids.contains(&id)
+    }
+}
+
+/// Returns `true` if the message is a post of the channel forwarded into its chat by Telegram.
+pub fn is_channel_post(msg: &Message, channel_id: ChatId) -> bool {
+    msg.is_automatic_forward()
+        && msg
+            .forward_from_chat()
+            .is_some_and(|chat| chat.id == channel_id)
+}
+
+/// Returns `true` if the message was sent into a thread of comments of a channel's post.
+/// Any reply in a supergroup has a thread, so the thread should start from a post of the channel.
+pub fn is_comment_to_post(msg: &Message, channel_id: ChatId, posts: &ChannelPosts) -> bool {
+    msg.reply_to_message()
+        .is_some_and(|reply| is_channel_post(reply, channel_id))
+        || msg.thread_id.is_some_and(|id| posts.contains(id))
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, ChatPermissions, UserId},
+    Bot, RequestError,
+};
+
+use crate::{captcha::Challenge, policy::lift_restrictions, storage::Storage};
+
+/// Newcomers who are able to only read chat until they are checked.
+/// They are kept in storage, so restrictions are lifted even if Bot was restarted in the middle of a check.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Quarantine {
+    /// Users and dates they were put in quarantine.
+    pending: BTreeMap<UserId, DateTime<Utc>>,
+    /// Questions users in quarantine should answer.
+    #[serde(default)]
+    challenges: BTreeMap<UserId, Challenge>,
+}
+
+impl Quarantine {
+    pub fn add(&mut self, user_id: UserId) {
+        self.pending.insert(user_id, Utc::now());
+    }
+
+    /// Returns `false` if user wasn't in quarantine.
+    pub fn remove(&mut self, user_id: UserId) -> bool {
+        self.challenges.remove(&user_id);
+
+        self.pending.remove(&user_id).is_some()
+    }
+
+    pub fn contains(&self, user_id: UserId) -> bool {
+        self.pending.contains_key(&user_id)
+    }
+
+    /// Users put in quarantine before the date who weren't asked a question.
+    /// Their check was interrupted, e.g. by a restart or a failed request.
+    pub fn unchecked(&self, added_before: DateTime<Utc>) -> Vec<UserId> {
+        self.pending
+            .iter()
+            .filter(|&(user_id, &added)| {
+                added < added_before && !self.challenges.contains_key(user_id)
+            })
+            .map(|(&user_id, _)| user_id)
+            .collect()
+    }
+
+    pub fn set_challenge(&mut self, challenge: Challenge) {
+        self.challenges.insert(challenge.user.id, challenge);
+    }
+
+    pub fn has_challenge(&self, user_id: UserId) -> bool {
+        self.challenges.contains_key(&user_id)
+    }
+
+    /// Returns the challenge of user, if there is one, so it can't be answered twice.
+    /// User stays in quarantine until the outcome of the answer is applied.
+    pub fn take_challenge(&mut self, user_id: UserId) -> Option<Challenge> {
+        self.challenges.remove(&user_id)
+    }
+
+    /// Challenges which weren't answered in time.
+    pub fn expired_challenges(&self, now: DateTime<Utc>) -> Vec<UserId> {
+        self.challenges
+            .values()
+            .filter(|challenge| challenge.deadline <= now)
+            .map(|challenge| challenge.user.id)
+            .collect()
+    }
+}
+
+/// Restricts user to read only until [`release`] is called.
+pub async fn put_in_quarantine(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    quarantine: &Storage<Quarantine>,
+) -> Result<(), RequestError> {
+    //
+    // user is remembered first, so a restart right after restriction doesn't leave them muted forever
+    //
+    quarantine
+        .update(|quarantine| quarantine.add(user_id))
+        .await;
+
+    if let Err(err) = bot
+        .restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
+        .await
+    {
+        quarantine
+            .update(|quarantine| quarantine.remove(user_id))
+            .await;
+
+        return Err(err);
+    }
+    log::debug!(
+        "{} has been put in quarantine in chat ({})",
+        user_id,
+        chat_id
+    );
+
+    Ok(())
+}
+
+/// Lifts restrictions of a user in quarantine. Users who aren't in quarantine aren't touched.
+pub async fn release(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    quarantine: &Storage<Quarantine>,
+) -> Result<(), RequestError> {
+    if !quarantine
+        .read(|quarantine| quarantine.contains(user_id))
+        .await
+    {
+        return Ok(());
+    }
+
+    lift_restrictions(bot, chat_id, user_id).await?;
+    quarantine
+        .update(|quarantine| quarantine.remove(user_id))
+        .await;
+    log::debug!(
+        "{} has been released from quarantine in chat ({})",
+        user_id,
+        chat_id
+    );
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, ChatPermissions},
+    Bot, RequestError,
+};
+use tokio::sync::Mutex;
+
+use crate::storage::Storage;
+
+/// Joins to chat during the last window of time.
+pub struct JoinRate {
+    window: Duration,
+    joins: Mutex<VecDeque<Instant>>,
+}
+
+impl JoinRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            joins: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Counts a join and returns the number of joins within the window, this one included.
+    pub async fn register(&self) -> usize {
+        let now = Instant::now();
+        let mut joins = self.joins.lock().await;
+
+        while joins
+            .front()
+            .is_some_and(|&join| now.duration_since(join) > self.window)
+        {
+            joins.pop_front();
+        }
+        joins.push_back(now);
+
+        joins.len()
+    }
+}
+
+/// State of chat during a raid. Newcomers are put in quarantine without being checked
+/// and all of them are checked once the lockdown is over.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Lockdown {
+    active: Option<ActiveLockdown>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActiveLockdown {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    /// Permissions of chat before the lockdown, if chat was made read only.
+    pub permissions: Option<ChatPermissions>,
+    /// Number of users who joined chat during the lockdown.
+    pub joined: usize,
+}
+
+impl Lockdown {
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    pub fn count_join(&mut self) {
+        if let Some(active) = &mut self.active {
+            active.joined += 1;
+        }
+    }
+
+    /// Returns `true` if the lockdown should be over by the date.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.active
+            .as_ref()
+            .is_some_and(|active| active.until <= now)
+    }
+}
+
+/// Starts a lockdown till the date. Chat becomes read only for everyone if it's requested.
+/// Returns `false` if a lockdown was already active, then nothing is changed.
+pub async fn lock(
+    bot: &Bot,
+    chat_id: ChatId,
+    until: DateTime<Utc>,
+    read_only: bool,
+    lockdown: &Storage<Lockdown>,
+) -> Result<bool, RequestError> {
+    //
+    // concurrent joins may reach the threshold at once, so only one of them starts the lockdown
+    //
+    let started = lockdown
+        .update(|lockdown| {
+            if lockdown.active.is_some() {
+                return false;
+            }
+
+            lockdown.active = Some(ActiveLockdown {
+                since: Utc::now(),
+                until,
+                permissions: None,
+                joined: 0,
+            });
+
+            true
+        })
+        .await;
+    if !started {
+        return Ok(false);
+    }
+    log::info!("Lockdown of chat ({}) started", chat_id);
+
+    if read_only {
+        let permissions = bot
+            .get_chat(chat_id)
+            .await?
+            .permissions()
+            .unwrap_or_else(ChatPermissions::all);
+
+        //
+        // permissions are saved before they are changed, so they are restored even if the change fails halfway
+        //
+        lockdown
+            .update(|lockdown| {
+                if let Some(active) = &mut lockdown.active {
+                    active.permissions = Some(permissions);
+                }
+            })
+            .await;
+
+        bot.set_chat_permissions(chat_id, ChatPermissions::empty())
+            .await?;
+        log::debug!("Chat ({}) has been made read only", chat_id);
+    }
+
+    Ok(true)
+}
+
+/// Ends the lockdown and gives chat back its permissions. Returns `None` if there was no lockdown.
+pub async fn unlock(
+    bot: &Bot,
+    chat_id: ChatId,
+    lockdown: &Storage<Lockdown>,
+) -> Result<Option<ActiveLockdown>, RequestError> {
+    let Some(active) = lockdown.read(|lockdown| lockdown.active.clone()).await else {
+        return Ok(None);
+    };
+
+    if let Some(permissions) = active.permissions {
+        bot.set_chat_permissions(chat_id, permissions).await?;
+        log::debug!("Permissions of chat ({}) have been restored", chat_id);
+    }
+
+    lockdown.update(|lockdown| lockdown.active = None).await;
+    log::info!("Lockdown of chat ({}) is over", chat_id);
+
+    Ok(Some(active))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::JoinRate;
+
+    #[tokio::test]
+    async fn counts_joins_within_window() {
+        let rate = JoinRate::new(Duration::from_millis(200));
+
+        assert_eq!(rate.register().await, 1);
+        assert_eq!(rate.register().await, 2);
+        assert_eq!(rate.register().await, 3);
+    }
+
+    #[tokio::test]
+    async fn forgets_joins_out_of_window() {
+        let rate = JoinRate::new(Duration::from_millis(50));
+
+        rate.register().await;
+        rate.register().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(rate.register().await, 1);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeSet;
+
+use teloxide::types::ChatMemberKind;
+
+const MANAGE_CHAT: &str = "управление чатом";
+const CHANGE_INFO: &str = "изменение информации";
+const POST_MESSAGES: &str = "публикация сообщений";
+const EDIT_MESSAGES: &str = "редактирование сообщений";
+const DELETE_MESSAGES: &str = "удаление сообщений";
+const MANAGE_VIDEO_CHATS: &str = "видеочаты";
+const INVITE_USERS: &str = "приглашение пользователей";
+const RESTRICT_MEMBERS: &str = "блокировка пользователей";
+const PIN_MESSAGES: &str = "закрепление сообщений";
+const MANAGE_TOPICS: &str = "управление темами";
+const PROMOTE_MEMBERS: &str = "назначение админов";
+
+const ALL: [&str; 11] = [
+    MANAGE_CHAT,
+    CHANGE_INFO,
+    POST_MESSAGES,
+    EDIT_MESSAGES,
+    DELETE_MESSAGES,
+    MANAGE_VIDEO_CHATS,
+    INVITE_USERS,
+    RESTRICT_MEMBERS,
+    PIN_MESSAGES,
+    MANAGE_TOPICS,
+    PROMOTE_MEMBERS,
+];
+
+/// Rights of an administrator in the way they are named in notifications. Owner has all of them.
+pub fn admin_rights(kind: &ChatMemberKind) -> BTreeSet<&'static str> {
+    match kind {
+        ChatMemberKind::Owner(_) => ALL.into_iter().collect(),
+        ChatMemberKind::Administrator(admin) => [
+            (admin.can_manage_chat, MANAGE_CHAT),
+            (admin.can_change_info, CHANGE_INFO),
+            (admin.can_post_messages, POST_MESSAGES),
+            (admin.can_edit_messages, EDIT_MESSAGES),
+            (admin.can_delete_messages, DELETE_MESSAGES),
+            (admin.can_manage_video_chats, MANAGE_VIDEO_CHATS),
+            (admin.can_invite_users, INVITE_USERS),
+            (admin.can_restrict_members, RESTRICT_MEMBERS),
+            (admin.can_pin_messages, PIN_MESSAGES),
+            (admin.can_manage_topics, MANAGE_TOPICS),
+            (admin.can_promote_members, PROMOTE_MEMBERS),
+        ]
+        .into_iter()
+        .filter_map(|(granted, right)| granted.then_some(right))
+        .collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// Rights which were granted and revoked by the change.
+pub fn diff(old: &ChatMemberKind, new: &ChatMemberKind) -> (Vec<&'static str>, Vec<&'static str>) {
+    let (old, new) = (admin_rights(old), admin_rights(new));
+
+    (
+        new.difference(&old).copied().collect(),
+        old.difference(&new).copied().collect(),
+    )
+}
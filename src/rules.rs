@@ -0,0 +1,492 @@
+use std::{collections::HashSet, sync::Arc};
+
+use teloxide::{
+    types::{ChatId, User, UserId},
+    Bot, RequestError,
+};
+
+use crate::{audit::JoinLink, config::Config, membership::MembershipCache, misc::BoxFuture};
+
+/// A criterion a user should match to be admitted into channel's chat.
+pub trait Rule: Send + Sync {
+    /// Rule as it's written in config.
+    fn name(&self) -> String;
+
+    /// Returns the name of a rule the user doesn't match, `None` if the user matches the rule.
+    /// `link` is the invite link the user joined the chat through, if it's known.
+    fn check<'a>(
+        &'a self,
+        bot: &'a Bot,
+        user: &'a User,
+        link: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>>;
+}
+
+/// Rules which don't need any requests to Telegram.
+trait SimpleRule: Send + Sync {
+    fn name(&self) -> String;
+
+    fn matches(&self, user: &User, link: Option<&JoinLink>) -> bool;
+}
+
+impl<T: SimpleRule> Rule for T {
+    fn name(&self) -> String {
+        SimpleRule::name(self)
+    }
+
+    fn check<'a>(
+        &'a self,
+        _: &'a Bot,
+        user: &'a User,
+        link: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        let failed = !self.matches(user, link);
+
+        Box::pin(async move { Ok(failed.then(|| SimpleRule::name(self))) })
+    }
+}
+
+/// User is a member of a channel or a chat.
+struct MemberOf {
+    kind: &'static str,
+    chat_id: ChatId,
+    members: Arc<MembershipCache>,
+}
+
+impl Rule for MemberOf {
+    fn name(&self) -> String {
+        format!("{}({})", self.kind, self.chat_id)
+    }
+
+    fn check<'a>(
+        &'a self,
+        bot: &'a Bot,
+        user: &'a User,
+        _: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(async move {
+            let member = self
+                .members
+                .get_chat_member(bot, self.chat_id, user.id)
+                .await?;
+
+            Ok((!member.is_present()).then(|| self.name()))
+        })
+    }
+}
+
+struct HasUsername;
+
+impl SimpleRule for HasUsername {
+    fn name(&self) -> String {
+        "has_username".to_owned()
+    }
+
+    fn matches(&self, user: &User, _: Option<&JoinLink>) -> bool {
+        user.username.is_some()
+    }
+}
+
+struct NotBot;
+
+impl SimpleRule for NotBot {
+    fn name(&self) -> String {
+        "not_bot".to_owned()
+    }
+
+    fn matches(&self, user: &User, _: Option<&JoinLink>) -> bool {
+        !user.is_bot
+    }
+}
+
+struct Premium;
+
+impl SimpleRule for Premium {
+    fn name(&self) -> String {
+        "premium".to_owned()
+    }
+
+    fn matches(&self, user: &User, _: Option<&JoinLink>) -> bool {
+        user.is_premium
+    }
+}
+
+struct InList {
+    kind: &'static str,
+    users: HashSet<UserId>,
+    /// `true` for allowlist, `false` for blocklist.
+    expected: bool,
+}
+
+impl SimpleRule for InList {
+    fn name(&self) -> String {
+        format!("{}(…)", self.kind)
+    }
+
+    fn matches(&self, user: &User, _: Option<&JoinLink>) -> bool {
+        self.users.contains(&user.id) == self.expected
+    }
+}
+
+/// User joined the chat through one of the invite links, given by names or URLs.
+struct InviteLink(Vec<String>);
+
+impl SimpleRule for InviteLink {
+    fn name(&self) -> String {
+        format!("invite_link({})", self.0.join(", "))
+    }
+
+    fn matches(&self, _: &User, link: Option<&JoinLink>) -> bool {
+        link.is_some_and(|link| self.0.iter().any(|pattern| link.matches(pattern)))
+    }
+}
+
+/// Ids are given in ascending order, so an id says how old an account is.
+struct IdBelow(u64);
+
+impl SimpleRule for IdBelow {
+    fn name(&self) -> String {
+        format!("id_below({})", self.0)
+    }
+
+    fn matches(&self, user: &User, _: Option<&JoinLink>) -> bool {
+        user.id.0 < self.0
+    }
+}
+
+struct Not(Box<dyn Rule>);
+
+impl Rule for Not {
+    fn name(&self) -> String {
+        format!("!{}", self.0.name())
+    }
+
+    fn check<'a>(
+        &'a self,
+        bot: &'a Bot,
+        user: &'a User,
+        link: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(async move {
+            Ok(match self.0.check(bot, user, link).await? {
+                Some(_) => None,
+                None => Some(self.name()),
+            })
+        })
+    }
+}
+
+/// User matches every rule. Rules are checked one by one until the first failed one.
+struct All(Vec<Box<dyn Rule>>);
+
+impl Rule for All {
+    fn name(&self) -> String {
+        format!("all({})", join_names(&self.0))
+    }
+
+    fn check<'a>(
+        &'a self,
+        bot: &'a Bot,
+        user: &'a User,
+        link: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(async move {
+            for rule in &self.0 {
+                if let Some(failed) = rule.check(bot, user, link).await? {
+                    return Ok(Some(failed));
+                }
+            }
+
+            Ok(None)
+        })
+    }
+}
+
+/// User matches at least one rule.
+struct Any(Vec<Box<dyn Rule>>);
+
+impl Rule for Any {
+    fn name(&self) -> String {
+        format!("any({})", join_names(&self.0))
+    }
+
+    fn check<'a>(
+        &'a self,
+        bot: &'a Bot,
+        user: &'a User,
+        link: Option<&'a JoinLink>,
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(async move {
+            for rule in &self.0 {
+                if rule.check(bot, user, link).await?.is_none() {
+                    return Ok(None);
+                }
+            }
+
+            Ok(Some(self.name()))
+        })
+    }
+}
+
+fn join_names(rules: &[Box<dyn Rule>]) -> String {
+    rules
+        .iter()
+        .map(|rule| rule.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Rules users should match to be admitted into channel's chat.
+pub struct Admission(All);
+
+impl Admission {
+    /// Parses rules separated by commas. All of them should be matched.
+    ///
+    /// Available rules:
+    /// - `subscribed` or `subscribed(<channel id>)` - a member of the channel (Bot's channel by default),
+    /// - `member(<chat id>)` - a member of the chat,
+    /// - `has_username`, `not_bot`, `premium`,
+    /// - `allowlist(<user id> ...)`, `blocklist(<user id> ...)`,
+    /// - `id_below(<user id>)` - the account is older than the one with the given id,
+    /// - `invite_link(<name or URL>, ...)` - the user joined the chat through one of the invite links,
+    /// - `all(<rule>, ...)`, `any(<rule>, ...)`, `!<rule>`.
+    pub fn parse(rules: &str, cfg: &Config, members: Arc<MembershipCache>) -> Result<Self, String> {
+        Ok(Self(All(parse_list(rules, &Context { cfg, members })?)))
+    }
+
+    /// Returns the name of a rule the user doesn't match, `None` if the user is admitted.
+    pub async fn check(
+        &self,
+        bot: &Bot,
+        user: &User,
+        link: Option<&JoinLink>,
+    ) -> Result<Option<String>, RequestError> {
+        self.0.check(bot, user, link).await
+    }
+}
+
+/// What rules need to be created.
+struct Context<'a> {
+    cfg: &'a Config,
+    members: Arc<MembershipCache>,
+}
+
+fn parse_list(list: &str, ctx: &Context) -> Result<Vec<Box<dyn Rule>>, String> {
+    let mut rules = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced parentheses: {}", list)),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                rules.push(parse_rule(&list[start..i], ctx)?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth > 0 {
+        return Err(format!("unbalanced parentheses: {}", list));
+    }
+
+    if !list[start..].trim().is_empty() {
+        rules.push(parse_rule(&list[start..], ctx)?);
+    }
+
+    Ok(rules)
+}
+
+fn parse_rule(rule: &str, ctx: &Context) -> Result<Box<dyn Rule>, String> {
+    let rule = rule.trim();
+
+    if let Some(negated) = rule.strip_prefix('!') {
+        return Ok(Box::new(Not(parse_rule(negated, ctx)?)));
+    }
+
+    let (name, args) = match rule.split_once('(') {
+        Some((name, args)) => (
+            name.trim(),
+            Some(
+                args.strip_suffix(')')
+                    .ok_or_else(|| format!("rule is not closed: {}", rule))?,
+            ),
+        ),
+        None => (rule, None),
+    };
+
+    let parse_id = |arg: &str| {
+        arg.trim()
+            .parse::<i64>()
+            .map_err(|err| format!("wrong id in {}: {}", rule, err))
+    };
+    let parse_user_id = |arg: &str| {
+        arg.trim()
+            .parse::<u64>()
+            .map_err(|err| format!("wrong user id in {}: {}", rule, err))
+    };
+    let parse_users = |args: &str| {
+        args.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| parse_user_id(arg).map(UserId))
+            .collect::<Result<HashSet<_>, _>>()
+    };
+
+    Ok(match (name, args) {
+        ("all", Some(args)) => Box::new(All(parse_list(args, ctx)?)),
+        ("any", Some(args)) => Box::new(Any(parse_list(args, ctx)?)),
+        ("subscribed", None) => Box::new(MemberOf {
+            kind: "subscribed",
+            chat_id: ctx.cfg.channel_id,
+            members: ctx.members.clone(),
+        }),
+        ("subscribed", Some(arg)) => Box::new(MemberOf {
+            kind: "subscribed",
+            chat_id: ChatId(parse_id(arg)?),
+            members: ctx.members.clone(),
+        }),
+        ("member", Some(arg)) => Box::new(MemberOf {
+            kind: "member",
+            chat_id: ChatId(parse_id(arg)?),
+            members: ctx.members.clone(),
+        }),
+        ("has_username", None) => Box::new(HasUsername),
+        ("not_bot", None) => Box::new(NotBot),
+        ("premium", None) => Box::new(Premium),
+        ("allowlist", Some(args)) => Box::new(InList {
+            kind: "allowlist",
+            users: parse_users(args)?,
+            expected: true,
+        }),
+        ("blocklist", Some(args)) => Box::new(InList {
+            kind: "blocklist",
+            users: parse_users(args)?,
+            expected: false,
+        }),
+        ("id_below", Some(arg)) => Box::new(IdBelow(parse_user_id(arg)?)),
+        ("invite_link", Some(args)) => Box::new(InviteLink(
+            args.split(',')
+                .map(|arg| arg.trim().to_owned())
+                .filter(|arg| !arg.is_empty())
+                .collect(),
+        )),
+        _ => return Err(format!("unknown rule: {}", rule)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use teloxide::types::{User, UserId};
+
+    use super::{parse_list, Context, InviteLink, SimpleRule};
+    use crate::{audit::JoinLink, config::Config, membership::MembershipCache};
+
+    fn parse(rules: &str) -> Result<Vec<String>, String> {
+        let cfg = Config::for_tests();
+        let ctx = Context {
+            cfg: &cfg,
+            members: Arc::new(MembershipCache::new(Duration::ZERO, false)),
+        };
+
+        parse_list(rules, &ctx).map(|rules| rules.iter().map(|rule| rule.name()).collect())
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(
+            parse("subscribed, has_username,not_bot"),
+            Ok(vec![
+                "subscribed(-1001)".to_owned(),
+                "has_username".to_owned(),
+                "not_bot".to_owned()
+            ])
+        );
+        assert_eq!(
+            parse("member(-100), id_below(5000000000), !premium"),
+            Ok(vec![
+                "member(-100)".to_owned(),
+                "id_below(5000000000)".to_owned(),
+                "!premium".to_owned()
+            ])
+        );
+        assert_eq!(
+            parse("allowlist(1 2, 3)"),
+            Ok(vec!["allowlist(…)".to_owned()])
+        );
+    }
+
+    #[test]
+    fn parses_nested_rules() {
+        assert_eq!(
+            parse("any(subscribed, member(-100)), not_bot"),
+            Ok(vec![
+                "any(subscribed(-1001), member(-100))".to_owned(),
+                "not_bot".to_owned()
+            ])
+        );
+        assert_eq!(
+            parse("all(!any(premium, has_username))"),
+            Ok(vec!["all(!any(premium, has_username))".to_owned()])
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_rules() {
+        assert!(parse("unknown").is_err());
+        assert!(parse("has_username(1)").is_err());
+        assert!(parse("id_below(abc)").is_err());
+        assert!(parse("allowlist(1, x)").is_err());
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses() {
+        assert!(parse("any(subscribed, premium").is_err());
+        assert!(parse("subscribed)").is_err());
+        assert!(parse("any(premium)), has_username").is_err());
+        assert!(parse("premium), any(has_username").is_err());
+    }
+
+    #[test]
+    fn parses_invite_links() {
+        assert_eq!(
+            parse("any(subscribed, invite_link(partners, https://t.me/+abc)), not_bot"),
+            Ok(vec![
+                "any(subscribed(-1001), invite_link(partners, https://t.me/+abc))".to_owned(),
+                "not_bot".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn matches_invite_links_by_name_or_url() {
+        let user = User {
+            id: UserId(42),
+            is_bot: false,
+            first_name: "Ivan".to_owned(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        let link = |name: Option<&str>| JoinLink {
+            url: "https://t.me/+abc".to_owned(),
+            name: name.map(str::to_owned),
+            creator_id: UserId(1),
+            creator: "@admin".to_owned(),
+        };
+        let rule = InviteLink(vec!["partners".to_owned(), "https://t.me/+xyz".to_owned()]);
+
+        assert!(rule.matches(&user, Some(&link(Some("partners")))));
+        assert!(!rule.matches(&user, Some(&link(Some("Partners")))));
+        assert!(!rule.matches(&user, Some(&link(None))));
+        assert!(!rule.matches(&user, None));
+        assert!(InviteLink(vec!["https://t.me/+abc".to_owned()]).matches(&user, Some(&link(None))));
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
+
+pub const ALLOW_SENDER_CHAT_CALLBACK_PREFIX: &str = "allowsender:";
+
+/// Work chat is notified about a chat again if it keeps writing after that many hours.
+const REPORT_INTERVAL_HOURS: i64 = 24;
+
+/// Chats (usually channels) users write on behalf of in channel's chat.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SenderChats {
+    /// Allowed by admins in addition to the ones listed in config.
+    allowed: BTreeSet<ChatId>,
+    /// When work chat was notified about a chat the last time.
+    #[serde(default)]
+    reported_at: BTreeMap<ChatId, DateTime<Utc>>,
+}
+
+impl SenderChats {
+    pub fn is_allowed(&self, id: ChatId) -> bool {
+        self.allowed.contains(&id)
+    }
+
+    /// Returns `false` if the chat was allowed already.
+    pub fn allow(&mut self, id: ChatId) -> bool {
+        self.reported_at.remove(&id);
+        self.allowed.insert(id)
+    }
+
+    /// Returns `false` if the chat wasn't allowed.
+    /// Work chat is notified about the next message on behalf of the chat.
+    pub fn disallow(&mut self, id: ChatId) -> bool {
+        self.reported_at.remove(&id);
+        self.allowed.remove(&id)
+    }
+
+    /// Returns `true` if work chat hasn't been notified about the chat recently.
+    pub fn is_report_due(&self, id: ChatId, now: DateTime<Utc>) -> bool {
+        self.reported_at
+            .get(&id)
+            .is_none_or(|&at| now - at >= Duration::hours(REPORT_INTERVAL_HOURS))
+    }
+
+    pub fn report(&mut self, id: ChatId, now: DateTime<Utc>) {
+        self.reported_at.insert(id, now);
+    }
+}
+
+pub fn allow_keyboard(id: ChatId) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Разрешить",
+        format!("{}{}", ALLOW_SENDER_CHAT_CALLBACK_PREFIX, id),
+    )]])
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+
+/// A piece of bot's state which survives restarts.
+/// It's kept in memory and written to a JSON file inside the data directory after every update.
+pub struct Storage<T> {
+    path: PathBuf,
+    data: Mutex<T>,
+}
+
+impl<T> Storage<T>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    pub fn open(dir: &Path, name: &str) -> Self {
+        let path = dir.join(format!("{}.json", name));
+
+        let data = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                //
+                // the file would be overwritten by the next update, so it's kept aside for recovery
+                //
+                let corrupt = path.with_extension("json.corrupt");
+                if let Err(rename_err) = fs::rename(&path, &corrupt) {
+                    panic!(
+                        "Failed to parse {:?} ({}) and to move it to {:?}: {}",
+                        path, err, corrupt, rename_err
+                    );
+                }
+                log::error!(
+                    "Failed to parse {:?}, it's moved to {:?}, starting with empty state: {}",
+                    path,
+                    corrupt,
+                    err
+                );
+
+                T::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::info!("{:?} doesn't exist yet, starting with empty state", path);
+
+                T::default()
+            }
+            //
+            // starting with empty state would overwrite the file which may be fine
+            //
+            Err(err) => panic!("Failed to read {:?}: {}", path, err),
+        };
+
+        Self {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    pub async fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&*self.data.lock().await)
+    }
+
+    pub async fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut data = self.data.lock().await;
+        let result = f(&mut data);
+
+        if let Err(err) = self.save(&data) {
+            log::error!("Failed to save {:?}: {}", self.path, err);
+        }
+
+        result
+    }
+
+    fn save(&self, data: &T) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        //
+        // write into a temporary file first to not corrupt state if the bot is stopped in the middle
+        //
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(data)?)?;
+        fs::rename(tmp, &self.path)
+    }
+}
@@ -0,0 +1,273 @@
+use teloxide::types::{ChatMemberKind, ChatMemberUpdated};
+
+/// What happened to a chat member according to [`ChatMemberUpdated`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// User wasn't in the chat and now is there.
+    Joined,
+    /// User was banned and now is in the chat, i.e. was unbanned and added or joined again.
+    RejoinedAfterBan,
+    /// User wasn't in the chat and was added there as an admin right away, e.g. a moderation bot.
+    JoinedAsAdmin,
+    /// User left the chat on their own.
+    Left,
+    /// User was removed from the chat by someone else, but isn't banned.
+    Kicked,
+    /// User was banned in the chat.
+    Banned,
+    /// User became restricted or their restrictions were changed.
+    Restricted,
+    /// Restrictions of the user were lifted.
+    Unrestricted,
+    /// User became an admin or the owner.
+    Promoted,
+    /// User was an admin or the owner and now is a regular member.
+    Demoted,
+    /// Anything else, e.g. rights of an admin were changed or a user who wasn't in the chat was unbanned.
+    Other,
+}
+
+impl Transition {
+    pub fn of(req: &ChatMemberUpdated) -> Self {
+        Self::classify(
+            &req.old_chat_member.kind,
+            &req.new_chat_member.kind,
+            req.from.id != req.new_chat_member.user.id,
+        )
+    }
+
+    /// `by_other` tells whether the change was made by someone else than the user.
+    pub fn classify(old: &ChatMemberKind, new: &ChatMemberKind, by_other: bool) -> Self {
+        use ChatMemberKind::*;
+
+        match (old.is_present(), new.is_present()) {
+            (false, true) if new.is_privileged() => Transition::JoinedAsAdmin,
+            (false, true) if old.is_banned() => Transition::RejoinedAfterBan,
+            (false, true) => Transition::Joined,
+            (true, false) if new.is_banned() => Transition::Banned,
+            (true, false) if by_other => Transition::Kicked,
+            (true, false) => Transition::Left,
+            (true, true) => match (old, new) {
+                (_, _) if !old.is_privileged() && new.is_privileged() => Transition::Promoted,
+                (_, _) if old.is_privileged() && !new.is_privileged() => Transition::Demoted,
+                (Restricted(old), Restricted(new)) if old == new => Transition::Other,
+                (_, Restricted(_)) => Transition::Restricted,
+                (Restricted(_), _) => Transition::Unrestricted,
+                _ => Transition::Other,
+            },
+            (false, false) => match (old, new) {
+                (Banned(_), Banned(_)) => Transition::Other,
+                (_, Banned(_)) => Transition::Banned,
+                (Restricted(old), Restricted(new)) if old == new => Transition::Other,
+                (_, Restricted(_)) => Transition::Restricted,
+                (Restricted(_), _) => Transition::Unrestricted,
+                _ => Transition::Other,
+            },
+        }
+    }
+
+    /// User is in the chat now as a regular member and wasn't there before.
+    /// Admins aren't checked, so [`Transition::JoinedAsAdmin`] isn't a join.
+    pub fn is_join(&self) -> bool {
+        matches!(self, Transition::Joined | Transition::RejoinedAfterBan)
+    }
+
+    /// User was in the chat and isn't there anymore.
+    pub fn is_departure(&self) -> bool {
+        matches!(
+            self,
+            Transition::Left | Transition::Kicked | Transition::Banned
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use teloxide::types::{Administrator, Banned, ChatMemberKind, Owner, Restricted, UntilDate};
+
+    use super::Transition::{self, *};
+
+    fn owner() -> ChatMemberKind {
+        ChatMemberKind::Owner(Owner {
+            custom_title: None,
+            is_anonymous: false,
+        })
+    }
+
+    fn admin(can_restrict_members: bool) -> ChatMemberKind {
+        ChatMemberKind::Administrator(Administrator {
+            custom_title: None,
+            is_anonymous: false,
+            can_be_edited: true,
+            can_manage_chat: true,
+            can_change_info: false,
+            can_post_messages: false,
+            can_edit_messages: false,
+            can_delete_messages: true,
+            can_manage_video_chats: false,
+            can_invite_users: true,
+            can_restrict_members,
+            can_pin_messages: false,
+            can_manage_topics: false,
+            can_promote_members: false,
+        })
+    }
+
+    fn member() -> ChatMemberKind {
+        ChatMemberKind::Member
+    }
+
+    fn restricted(is_member: bool, can_send_messages: bool) -> ChatMemberKind {
+        ChatMemberKind::Restricted(Restricted {
+            until_date: UntilDate::Forever,
+            is_member,
+            can_send_messages,
+            can_send_media_messages: false,
+            can_send_other_messages: false,
+            can_add_web_page_previews: false,
+            can_change_info: false,
+            can_invite_users: false,
+            can_pin_messages: false,
+            can_manage_topics: false,
+            can_send_polls: false,
+        })
+    }
+
+    fn left() -> ChatMemberKind {
+        ChatMemberKind::Left
+    }
+
+    fn banned(forever: bool) -> ChatMemberKind {
+        ChatMemberKind::Banned(Banned {
+            until_date: if forever {
+                UntilDate::Forever
+            } else {
+                UntilDate::Date(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap())
+            },
+        })
+    }
+
+    fn classify(old: ChatMemberKind, new: ChatMemberKind) -> Transition {
+        Transition::classify(&old, &new, false)
+    }
+
+    fn classify_by_other(old: ChatMemberKind, new: ChatMemberKind) -> Transition {
+        Transition::classify(&old, &new, true)
+    }
+
+    #[test]
+    fn joins() {
+        assert_eq!(classify(left(), member()), Joined);
+        assert_eq!(classify_by_other(left(), member()), Joined);
+        assert_eq!(classify(left(), restricted(true, false)), Joined);
+        assert_eq!(classify(restricted(false, false), member()), Joined);
+        assert_eq!(
+            classify(restricted(false, false), restricted(true, false)),
+            Joined
+        );
+    }
+
+    #[test]
+    fn joins_as_admin() {
+        assert_eq!(classify_by_other(left(), admin(true)), JoinedAsAdmin);
+        assert_eq!(classify(left(), owner()), JoinedAsAdmin);
+        assert_eq!(
+            classify_by_other(restricted(false, false), admin(false)),
+            JoinedAsAdmin
+        );
+        assert_eq!(classify_by_other(banned(true), admin(false)), JoinedAsAdmin);
+        assert!(!JoinedAsAdmin.is_join());
+    }
+
+    #[test]
+    fn rejoins_after_ban() {
+        assert_eq!(classify(banned(true), member()), RejoinedAfterBan);
+        assert_eq!(classify(banned(false), member()), RejoinedAfterBan);
+        assert_eq!(
+            classify(banned(true), restricted(true, false)),
+            RejoinedAfterBan
+        );
+    }
+
+    #[test]
+    fn departures() {
+        assert_eq!(classify(member(), left()), Left);
+        assert_eq!(classify(restricted(true, false), left()), Left);
+        assert_eq!(
+            classify(restricted(true, false), restricted(false, false)),
+            Left
+        );
+        assert_eq!(classify(admin(true), left()), Left);
+        assert_eq!(classify(owner(), left()), Left);
+
+        assert_eq!(classify_by_other(member(), left()), Kicked);
+        assert_eq!(classify_by_other(restricted(true, true), left()), Kicked);
+        assert_eq!(classify_by_other(admin(false), left()), Kicked);
+
+        assert_eq!(classify_by_other(member(), banned(true)), Banned);
+        assert_eq!(classify_by_other(member(), banned(false)), Banned);
+        assert_eq!(
+            classify_by_other(restricted(true, false), banned(true)),
+            Banned
+        );
+        assert_eq!(classify_by_other(admin(true), banned(true)), Banned);
+    }
+
+    #[test]
+    fn restrictions() {
+        assert_eq!(
+            classify_by_other(member(), restricted(true, false)),
+            Restricted
+        );
+        assert_eq!(
+            classify_by_other(restricted(true, false), restricted(true, true)),
+            Restricted
+        );
+        assert_eq!(
+            classify_by_other(restricted(true, false), member()),
+            Unrestricted
+        );
+
+        // user isn't in the chat, but is restricted in advance
+        assert_eq!(
+            classify_by_other(left(), restricted(false, false)),
+            Restricted
+        );
+        assert_eq!(
+            classify_by_other(restricted(false, false), left()),
+            Unrestricted
+        );
+    }
+
+    #[test]
+    fn promotions() {
+        assert_eq!(classify_by_other(member(), admin(false)), Promoted);
+        assert_eq!(
+            classify_by_other(restricted(true, false), admin(false)),
+            Promoted
+        );
+        assert_eq!(classify_by_other(admin(true), owner()), Other);
+
+        assert_eq!(classify_by_other(admin(false), member()), Demoted);
+        assert_eq!(
+            classify_by_other(admin(false), restricted(true, false)),
+            Demoted
+        );
+        assert_eq!(classify_by_other(owner(), member()), Demoted);
+    }
+
+    #[test]
+    fn others() {
+        assert_eq!(classify_by_other(admin(false), admin(true)), Other);
+        assert_eq!(classify(member(), member()), Other);
+        assert_eq!(classify(left(), left()), Other);
+        assert_eq!(classify_by_other(banned(true), left()), Other);
+        assert_eq!(classify_by_other(banned(true), banned(false)), Other);
+        assert_eq!(classify_by_other(left(), banned(true)), Banned);
+        assert_eq!(
+            classify_by_other(restricted(true, false), restricted(true, false)),
+            Other
+        );
+    }
+}
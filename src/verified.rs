@@ -0,0 +1,44 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use teloxide::types::UserId;
+use tokio::sync::Mutex;
+
+/// Users who were checked recently, so their messages don't cause a check every time.
+pub struct VerifiedUsers {
+    ttl: Duration,
+    users: Mutex<HashMap<UserId, Instant>>,
+}
+
+impl VerifiedUsers {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn is_verified(&self, user_id: UserId) -> bool {
+        let mut users = self.users.lock().await;
+
+        match users.get(&user_id) {
+            Some(verified_at) if verified_at.elapsed() < self.ttl => true,
+            Some(_) => {
+                users.remove(&user_id);
+
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub async fn verify(&self, user_id: UserId) {
+        self.users.lock().await.insert(user_id, Instant::now());
+    }
+
+    pub async fn forget(&self, user_id: UserId) {
+        self.users.lock().await.remove(&user_id);
+    }
+}